@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+use chip8::Display;
+use palette::Palette;
+
+// the display in palette colors, each CHIP-8 pixel scale times larger
+pub struct Image {
+	pub width: usize,
+	pub height: usize,
+	// framebuffer values
+	pub pixels: Vec<u8>,
+	pub palette: Palette
+}
+
+impl Image {
+	pub fn capture(display: &Display, palette: Palette, scale: usize) -> Image {
+		let (width, height) = (display.width() * scale, display.height() * scale);
+		let mut pixels = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				pixels.push(display.pixel(x / scale, y / scale));
+			}
+		}
+
+		Image { width, height, pixels, palette }
+	}
+
+	pub fn save_png(&self, path: &Path) -> Result<(), String> {
+		File::create(path)
+			.and_then(|file| self.write_png(&mut BufWriter::new(file)))
+			.map_err(|error| format!("can't save '{}': {}", path.display(), error))
+	}
+
+	// RGB with uncompressed deflate blocks, the pictures are tiny
+	pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let mut scanlines = Vec::with_capacity((self.width * 3 + 1) * self.height);
+		for row in self.pixels.chunks(self.width) {
+			// no filter
+			scanlines.push(0);
+			for &value in row {
+				let (r, g, b) = self.palette.rgb(value);
+				scanlines.extend_from_slice(&[r, g, b]);
+			}
+		}
+
+		let mut zlib = vec![0x78, 0x01];
+		let blocks: Vec<&[u8]> = scanlines.chunks(0xFFFF).collect();
+		for (index, block) in blocks.iter().enumerate() {
+			let length = block.len() as u16;
+			zlib.push((index == blocks.len() - 1) as u8);
+			zlib.extend_from_slice(&[length as u8, (length >> 8) as u8, !length as u8, (!length >> 8) as u8]);
+			zlib.extend_from_slice(block);
+		}
+		zlib.extend_from_slice(&u32_be(adler32(&scanlines)));
+
+		let mut header = Vec::new();
+		header.extend_from_slice(&u32_be(self.width as u32));
+		header.extend_from_slice(&u32_be(self.height as u32));
+		// 8 bits per channel, RGB, default compression, filtering and no interlacing
+		header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+		writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+		write_png_chunk(writer, b"IHDR", &header)?;
+		write_png_chunk(writer, b"IDAT", &zlib)?;
+		write_png_chunk(writer, b"IEND", &[])?;
+		writer.flush()
+	}
+}
+
+fn u32_be(value: u32) -> [u8; 4] {
+	[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+	let mut checked = kind.to_vec();
+	checked.extend_from_slice(data);
+	writer.write_all(&u32_be(data.len() as u32))?;
+	writer.write_all(&checked)?;
+	writer.write_all(&u32_be(crc32(&checked)))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut table = [0u32; 256];
+	for (index, entry) in table.iter_mut().enumerate() {
+		*entry = (0..8).fold(index as u32, |crc, _| if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 });
+	}
+	!data.iter().fold(0xFFFFFFFF, |crc, &byte| table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+	let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+		let a = (a + byte as u32) % 65521;
+		(a, (b + a) % 65521)
+	});
+	(b << 16) | a
+}
+
+enum Format {
+	// frames showing for less than the 2/100 s browsers honor are dropped
+	Gif { pending: Option<(Vec<u8>, u64)> },
+	// raw 4:4:4 video at 60 frames per second
+	Y4m
+}
+
+// records the frames presented by a frontend, the file is complete once finished or dropped
+pub struct Recorder {
+	writer: BufWriter<File>,
+	format: Format,
+	palette: Palette,
+	scale: usize,
+	size: Option<(usize, usize)>,
+	frame: u64
+}
+
+impl Recorder {
+	// the format comes from the extension, .gif or .y4m
+	pub fn create(path: &Path, palette: Palette, scale: usize) -> Result<Recorder, String> {
+		let format = match path.extension().and_then(|extension| extension.to_str()) {
+			Some("gif") => Format::Gif { pending: None },
+			Some("y4m") => Format::Y4m,
+			_ => return Err(format!("can't record to '{}', expected a .gif or .y4m file", path.display()))
+		};
+		let file = File::create(path).map_err(|error| format!("can't record to '{}': {}", path.display(), error))?;
+
+		Ok(Recorder {
+			writer: BufWriter::new(file),
+			format,
+			palette,
+			scale,
+			size: None,
+			frame: 0
+		})
+	}
+
+	pub fn record(&mut self, display: &Display) -> io::Result<()> {
+		let image = Image::capture(display, self.palette, self.scale);
+		if self.size.is_none() {
+			self.write_header(&image)?;
+			self.size = Some((image.width, image.height));
+		}
+
+		let frame = self.frame;
+		self.frame += 1;
+		match self.format {
+			Format::Gif { ref mut pending } => {
+				let centiseconds = |frame: u64| frame * 100 / 60;
+				match pending.take() {
+					// unchanged, the pending frame shows longer
+					Some((pixels, start)) if pixels == image.pixels => *pending = Some((pixels, start)),
+					Some((pixels, start)) if centiseconds(frame) - centiseconds(start) >= 2 => {
+						write_gif_frame(&mut self.writer, &image, &pixels, (centiseconds(frame) - centiseconds(start)) as u16)?;
+						*pending = Some((image.pixels, frame));
+					},
+					Some((_, start)) => *pending = Some((image.pixels, start)),
+					None => *pending = Some((image.pixels, frame))
+				}
+				Ok(())
+			},
+			Format::Y4m => {
+				self.writer.write_all(b"FRAME\n")?;
+				let colors: Vec<(u8, u8, u8)> = (0..4).map(|value| ycbcr(self.palette.rgb(value))).collect();
+				// Y, Cb and Cr of the 4 colors
+				let planes: [Vec<u8>; 3] = [
+					colors.iter().map(|color| color.0).collect(),
+					colors.iter().map(|color| color.1).collect(),
+					colors.iter().map(|color| color.2).collect()
+				];
+				for plane in planes.iter() {
+					let bytes: Vec<u8> = image.pixels.iter().map(|&value| plane[value as usize & 3]).collect();
+					self.writer.write_all(&bytes)?;
+				}
+				Ok(())
+			}
+		}
+	}
+
+	fn write_header(&mut self, image: &Image) -> io::Result<()> {
+		match self.format {
+			Format::Gif { .. } => {
+				let (width, height) = (image.width as u16, image.height as u16);
+				self.writer.write_all(b"GIF89a")?;
+				self.writer.write_all(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8])?;
+				// global color table of 4 colors
+				self.writer.write_all(&[0x91, 0, 0])?;
+				for value in 0..4 {
+					let (r, g, b) = self.palette.rgb(value);
+					self.writer.write_all(&[r, g, b])?;
+				}
+				// loops forever
+				self.writer.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")
+			},
+			Format::Y4m => writeln!(self.writer, "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444", image.width, image.height)
+		}
+	}
+
+	pub fn finish(&mut self) -> io::Result<()> {
+		if let Format::Gif { ref mut pending } = self.format {
+			if let Some((pixels, start)) = pending.take() {
+				let image = Image { width: self.size.unwrap().0, height: self.size.unwrap().1, pixels: Vec::new(), palette: self.palette };
+				let delay = (self.frame - start) * 100 / 60;
+				write_gif_frame(&mut self.writer, &image, &pixels, if delay < 2 { 2 } else { delay as u16 })?;
+			}
+			if self.size.is_some() {
+				self.writer.write_all(b"\x3B")?;
+				self.size = None;
+			}
+		}
+		self.writer.flush()
+	}
+}
+
+impl Drop for Recorder {
+	fn drop(&mut self) {
+		if let Err(error) = self.finish() {
+			eprintln!("can't finish the recording: {}", error);
+		}
+	}
+}
+
+// BT.601 limited range
+fn ycbcr((r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+	let (r, g, b) = (r as f32, g as f32, b as f32);
+	let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+	let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+	let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+	(y.round() as u8, cb.round() as u8, cr.round() as u8)
+}
+
+fn write_gif_frame<W: Write>(writer: &mut W, image: &Image, pixels: &[u8], delay: u16) -> io::Result<()> {
+	let (width, height) = (image.width as u16, image.height as u16);
+	writer.write_all(&[0x21, 0xF9, 0x04, 0x00, delay as u8, (delay >> 8) as u8, 0x00, 0x00])?;
+	writer.write_all(&[0x2C, 0, 0, 0, 0, width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8, 0x00])?;
+
+	const MINIMUM_CODE_SIZE: u8 = 2;
+	writer.write_all(&[MINIMUM_CODE_SIZE])?;
+	for block in lzw(pixels, MINIMUM_CODE_SIZE).chunks(255) {
+		writer.write_all(&[block.len() as u8])?;
+		writer.write_all(block)?;
+	}
+	writer.write_all(&[0x00])
+}
+
+// codes packed least significant bits first
+struct BitWriter {
+	output: Vec<u8>,
+	bits: u32,
+	bit_count: u8
+}
+
+impl BitWriter {
+	fn write(&mut self, code: u16, code_size: u8) {
+		self.bits |= (code as u32) << self.bit_count;
+		self.bit_count += code_size;
+		while self.bit_count >= 8 {
+			self.output.push(self.bits as u8);
+			self.bits >>= 8;
+			self.bit_count -= 8;
+		}
+	}
+
+	fn finish(mut self) -> Vec<u8> {
+		if self.bit_count > 0 {
+			self.output.push(self.bits as u8);
+		}
+		self.output
+	}
+}
+
+// GIF flavor: variable code size up to 12 bits
+fn lzw(indices: &[u8], minimum_code_size: u8) -> Vec<u8> {
+	let clear_code: u16 = 1 << minimum_code_size;
+	let end_code = clear_code + 1;
+
+	let mut writer = BitWriter { output: Vec::new(), bits: 0, bit_count: 0 };
+	let mut code_size = minimum_code_size + 1;
+	let mut next_code = end_code + 1;
+	let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+
+	writer.write(clear_code, code_size);
+	let mut prefix: Option<u16> = None;
+	for &index in indices {
+		let current = match prefix {
+			None => {
+				prefix = Some(index as u16);
+				continue;
+			},
+			Some(current) => current
+		};
+		if let Some(&code) = table.get(&(current, index)) {
+			prefix = Some(code);
+			continue;
+		}
+
+		writer.write(current, code_size);
+		// the decoder widens its codes one entry late
+		if next_code > (1 << code_size) - 1 && code_size < 12 {
+			code_size += 1;
+		}
+		if next_code < 4096 {
+			table.insert((current, index), next_code);
+			next_code += 1;
+		} else {
+			writer.write(clear_code, code_size);
+			table.clear();
+			code_size = minimum_code_size + 1;
+			next_code = end_code + 1;
+		}
+		prefix = Some(index as u16);
+	}
+
+	if let Some(current) = prefix {
+		writer.write(current, code_size);
+		// the decoder adds an entry for the last code too before reading the end code
+		if next_code > (1 << code_size) - 1 && code_size < 12 {
+			code_size += 1;
+		}
+	}
+	writer.write(end_code, code_size);
+	writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+
+	// GIF LZW as decoders read it, None when the codes run out before the end code
+	fn decode_lzw(data: &[u8], minimum_code_size: u8) -> Option<Vec<u8>> {
+		let clear_code = 1 << minimum_code_size;
+		let end_code = clear_code + 1;
+		let initial_table: Vec<Vec<u8>> = (0..end_code + 1).map(|code| vec![code as u8]).collect();
+		let mut table = initial_table.clone();
+		let mut code_size = minimum_code_size + 1;
+		let mut previous: Option<Vec<u8>> = None;
+		let mut output = Vec::new();
+		let mut position = 0;
+
+		loop {
+			if position + code_size as usize > data.len() * 8 {
+				return None;
+			}
+			let code = (0..code_size as usize).fold(0, |code, bit| {
+				let bit_position = position + bit;
+				code | (((data[bit_position / 8] >> (bit_position % 8)) as usize & 1) << bit)
+			});
+			position += code_size as usize;
+
+			if code == clear_code {
+				table = initial_table.clone();
+				code_size = minimum_code_size + 1;
+				previous = None;
+				continue;
+			}
+			if code == end_code {
+				return Some(output);
+			}
+			let entry = match previous {
+				_ if code < table.len() => table[code].clone(),
+				Some(ref previous) if code == table.len() => {
+					let mut entry = previous.clone();
+					entry.push(previous[0]);
+					entry
+				},
+				_ => return None
+			};
+			output.extend_from_slice(&entry);
+			if let Some(mut new_entry) = previous.take() {
+				if table.len() < 4096 {
+					new_entry.push(entry[0]);
+					table.push(new_entry);
+				}
+			}
+			if table.len() == 1 << code_size && code_size < 12 {
+				code_size += 1;
+			}
+			previous = Some(entry);
+		}
+	}
+
+	#[test]
+	fn lzw_round_trip() {
+		// xorshift, the same inputs on every run
+		let mut state: u64 = 0x9E3779B97F4A7C15;
+		let mut random = move |limit: u64| {
+			state ^= state << 13;
+			state ^= state >> 7;
+			state ^= state << 17;
+			state % limit
+		};
+		for stream in 0..3000 {
+			// fewer values make longer strings, a few long streams fill the table and clear it
+			let values = random(4) + 1;
+			let length = if stream % 100 == 0 { 20000 } else { random(2000) as usize };
+			let indices: Vec<u8> = (0..length).map(|_| random(values) as u8).collect();
+			assert_eq!(decode_lzw(&lzw(&indices, 2), 2), Some(indices));
+		}
+	}
+
+	#[test]
+	fn checksums() {
+		assert_eq!(crc32(b"123456789"), 0xCBF43926);
+		assert_eq!(crc32(b"IEND"), 0xAE426082);
+		assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+		assert_eq!(adler32(&[]), 1);
+	}
+
+	#[test]
+	fn png_of_a_pixel() {
+		let image = Image { width: 1, height: 1, pixels: vec![1], palette: Palette::preset("high-contrast").unwrap() };
+		let mut png = Vec::new();
+		image.write_png(&mut png).unwrap();
+
+		let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+		expected.extend_from_slice(b"\x00\x00\x00\x0DIHDR\x00\x00\x00\x01\x00\x00\x00\x01\x08\x02\x00\x00\x00\x90\x77\x53\xDE");
+		// a stored deflate block of the filter byte and a white pixel
+		expected.extend_from_slice(b"\x00\x00\x00\x0FIDAT\x78\x01\x01\x04\x00\xFB\xFF\x00\xFF\xFF\xFF\x05\xFE\x02\xFE\x49\x66\x6E\x2B");
+		expected.extend_from_slice(b"\x00\x00\x00\x00IEND\xAE\x42\x60\x82");
+		assert_eq!(png, expected);
+	}
+
+	#[test]
+	fn y4m_header_and_frames() {
+		let path = env::temp_dir().join(format!("chip8dynarec-{}.y4m", std::process::id()));
+		let palette = Palette::preset("high-contrast").unwrap();
+		{
+			let mut recorder = Recorder::create(&path, palette, 2).unwrap();
+			let display = Display::new();
+			recorder.record(&display).unwrap();
+			recorder.record(&display).unwrap();
+		}
+		let video = fs::read(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+		assert_eq!(&video[..header.len()], &header[..]);
+		let frame_size = b"FRAME\n".len() + 3 * 128 * 64;
+		assert_eq!(video.len(), header.len() + 2 * frame_size);
+		let frame = &video[header.len() + frame_size..];
+		assert_eq!(&frame[..6], b"FRAME\n");
+		// black in BT.601 limited range
+		assert_eq!((frame[6], frame[6 + 128 * 64], frame[6 + 2 * 128 * 64]), (16, 128, 128));
+	}
+}
@@ -1,25 +1,104 @@
-extern crate sdl2;
-
+use std::cmp;
+use std::fmt;
+use std::fs;
 use std::fs::File;
-use std::io::prelude::*;
-use std::time::{Duration, Instant};
+use std::io::{Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(feature="debugger")]
-use std::process::Command;
+use chip8::debugger::Debugger;
 
 use chip8::MEMORY_SIZE;
 use chip8::ROM_START_ADDRESS;
 use chip8::keyboard::Keyboard;
-use chip8::display::Display;
-
-#[cfg(feature="interpreter")]
+use chip8::display::{Display, DISPLAY_HEIGHT};
+use chip8::frontend::{Action, Frontend, Headless};
+use chip8::quirks::Quirks;
+use chip8::savestate::{StateReader, StateWriter};
+use chip8::rewind::Rewind;
+use chip8::movie::Movie;
+use chip8::random::Random;
 use chip8::interpreter::Interpreter;
-
-#[cfg(not(feature="interpreter"))]
+use chip8::threadedinterpreter::ThreadedInterpreter;
 use chip8::recompiler::Recompiler;
 
 const STACK_SIZE: usize = 16;
 const V_REGISTERS_COUNT: usize = 16;
+const DEFAULT_CYCLES_PER_FRAME: u32 = 10;
+// 20 seconds of snapshots, one per frame
+const DEFAULT_REWIND_CAPACITY: usize = 20 * 60;
+
+// 4x5 sprites of the hexadecimal digits, at the start of memory
+const FONT: [u8; 80] = [
+	0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+	0x20, 0x60, 0x20, 0x20, 0x70, // 1
+	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+	0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+	0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+	0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+	0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+// FNV-1a, stable across builds to remember per ROM settings
+pub fn rom_hash(rom: &[u8]) -> u64 {
+	rom.iter().fold(0xCBF29CE484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Engine {
+	Interpreter,
+	Threaded,
+	Recompiler
+}
+
+impl Default for Engine {
+	// picked at build time with the interpreter and threaded features
+	fn default() -> Engine {
+		if cfg!(feature="interpreter") {
+			Engine::Interpreter
+		} else if cfg!(feature="threaded") {
+			Engine::Threaded
+		} else {
+			Engine::Recompiler
+		}
+	}
+}
+
+impl FromStr for Engine {
+	type Err = String;
+
+	fn from_str(name: &str) -> Result<Engine, String> {
+		match name {
+			"interpreter" => Ok(Engine::Interpreter),
+			"threaded" => Ok(Engine::Threaded),
+			"recompiler" => Ok(Engine::Recompiler),
+			_ => Err(format!("unknown engine '{}', expected interpreter, threaded or recompiler", name))
+		}
+	}
+}
+
+impl fmt::Display for Engine {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str(match *self {
+			Engine::Interpreter => "interpreter",
+			Engine::Threaded => "threaded",
+			Engine::Recompiler => "recompiler"
+		})
+	}
+}
 
 pub struct Chip8 {
 	pub memory: [u8; MEMORY_SIZE],
@@ -32,13 +111,46 @@ pub struct Chip8 {
 	pub register_sp: u8,
 	pub keyboard: Keyboard,
 	pub display: Display,
+	pub engine: Engine,
+	// set before running, the recompiler is rebuilt when they change
+	pub quirks: Quirks,
+	// instructions executed between two timer ticks
+	pub cycles_per_frame: u32,
+	// CXNN numbers are the same on every run from the same seed, applied on reset
+	pub seed: u64,
+	// receives every executed instruction, ignored by the recompiler
+	pub trace: Option<Box<dyn Write>>,
+	// where the save state slots of the hotkeys are stored, none without it
+	pub state_directory: Option<PathBuf>,
+	// filled by run(), none to turn rewinding off
+	pub rewind: Option<Rewind>,
+	// recorded or played, set with record_movie and play_movie
+	movie: Option<Movie>,
+	// stops the interpreters before the first instruction
+	#[cfg(feature="debugger")]
+	debugger: Option<Debugger>,
+	// reloaded on reset
+	rom: Vec<u8>,
+	random: Random,
+	frame_cycles: u32,
+	remaining_cycles: u32,
+	// skipped instructions the recompiler counted in its blocks, taken back on the next refresh
+	pub(crate) skipped_cycles: u32,
+	// key pressed during FX0A, waiting for its release
+	pressed_key: Option<u8>,
+	frame_count: u64,
+	cycle_count: u64,
+	quit: bool,
+	frontend: Box<dyn Frontend>,
+	threaded_interpreter: Option<Box<ThreadedInterpreter>>,
+	recompiler: Option<Box<Recompiler>>,
 	time_last_frame: Instant
 }
 
 impl Chip8 {
-	pub fn new() -> Chip8 {
-		let sdl_context = sdl2::init().unwrap();
-		
+	pub fn new(frontend: Box<dyn Frontend>) -> Chip8 {
+		// a different game every run unless seeded
+		let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() ^ (time.subsec_nanos() as u64) << 32).unwrap_or(0);
 		let mut chip8 = Chip8 {
 			memory: [0; MEMORY_SIZE],
 			stack: [0; STACK_SIZE],
@@ -48,136 +160,358 @@ impl Chip8 {
 			register_st: 0,
 			register_pc: ROM_START_ADDRESS,
 			register_sp: 0xFF,
-			keyboard: Keyboard::new(&sdl_context),
-			display: Display::new(&sdl_context),
+			keyboard: Keyboard::new(),
+			display: Display::new(),
+			engine: Engine::default(),
+			quirks: Quirks::default(),
+			cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+			seed,
+			trace: None,
+			state_directory: None,
+			rewind: Some(Rewind::new(1, DEFAULT_REWIND_CAPACITY)),
+			movie: None,
+			#[cfg(feature="debugger")]
+			debugger: Some(Debugger::new()),
+			rom: Vec::new(),
+			random: Random::new(seed),
+			frame_cycles: 0,
+			remaining_cycles: 0,
+			skipped_cycles: 0,
+			pressed_key: None,
+			frame_count: 0,
+			cycle_count: 0,
+			quit: false,
+			frontend,
+			threaded_interpreter: None,
+			recompiler: None,
 			time_last_frame: Instant::now()
 		};
 
-		chip8.memory[0x00] = 0xF0;
-		chip8.memory[0x01] = 0x90;
-		chip8.memory[0x02] = 0x90;
-		chip8.memory[0x03] = 0x90;
-		chip8.memory[0x04] = 0xF0;
-
-		chip8.memory[0x05] = 0x20;
-		chip8.memory[0x06] = 0x60;
-		chip8.memory[0x07] = 0x20;
-		chip8.memory[0x08] = 0x20;
-		chip8.memory[0x09] = 0x70;
-
-		chip8.memory[0x0A] = 0xF0;
-		chip8.memory[0x0B] = 0x10;
-		chip8.memory[0x0C] = 0xF0;
-		chip8.memory[0x0D] = 0x80;
-		chip8.memory[0x0E] = 0xF0;
-
-		chip8.memory[0x0F] = 0xF0;
-		chip8.memory[0x10] = 0x10;
-		chip8.memory[0x11] = 0xF0;
-		chip8.memory[0x12] = 0x10;
-		chip8.memory[0x13] = 0xF0;
-
-		chip8.memory[0x14] = 0x90;
-		chip8.memory[0x15] = 0x90;
-		chip8.memory[0x16] = 0xF0;
-		chip8.memory[0x17] = 0x10;
-		chip8.memory[0x18] = 0x10;
-
-		chip8.memory[0x19] = 0xF0;
-		chip8.memory[0x1A] = 0x80;
-		chip8.memory[0x1B] = 0xF0;
-		chip8.memory[0x1C] = 0x10;
-		chip8.memory[0x1D] = 0xF0;
-
-		chip8.memory[0x1E] = 0xF0;
-		chip8.memory[0x1F] = 0x80;
-		chip8.memory[0x20] = 0xF0;
-		chip8.memory[0x21] = 0x90;
-		chip8.memory[0x22] = 0xF0;
-
-		chip8.memory[0x23] = 0xF0;
-		chip8.memory[0x24] = 0x10;
-		chip8.memory[0x25] = 0x20;
-		chip8.memory[0x26] = 0x40;
-		chip8.memory[0x27] = 0x40;
-
-		chip8.memory[0x28] = 0xF0;
-		chip8.memory[0x29] = 0x90;
-		chip8.memory[0x2A] = 0xF0;
-		chip8.memory[0x2B] = 0x90;
-		chip8.memory[0x2C] = 0xF0;
-
-		chip8.memory[0x2D] = 0xF0;
-		chip8.memory[0x2E] = 0x90;
-		chip8.memory[0x2F] = 0xF0;
-		chip8.memory[0x30] = 0x10;
-		chip8.memory[0x31] = 0xF0;
-
-		chip8.memory[0x32] = 0xF0;
-		chip8.memory[0x33] = 0x90;
-		chip8.memory[0x34] = 0xF0;
-		chip8.memory[0x35] = 0x90;
-		chip8.memory[0x36] = 0x90;
-
-		chip8.memory[0x37] = 0xE0;
-		chip8.memory[0x38] = 0x90;
-		chip8.memory[0x39] = 0xE0;
-		chip8.memory[0x3A] = 0x90;
-		chip8.memory[0x3B] = 0xE0;
-
-		chip8.memory[0x3C] = 0xF0;
-		chip8.memory[0x3D] = 0x80;
-		chip8.memory[0x3E] = 0x80;
-		chip8.memory[0x3F] = 0x80;
-		chip8.memory[0x40] = 0xF0;
-
-		chip8.memory[0x41] = 0xE0;
-		chip8.memory[0x42] = 0x90;
-		chip8.memory[0x43] = 0x90;
-		chip8.memory[0x44] = 0x90;
-		chip8.memory[0x45] = 0xE0;
-
-		chip8.memory[0x46] = 0xF0;
-		chip8.memory[0x47] = 0x80;
-		chip8.memory[0x48] = 0xF0;
-		chip8.memory[0x49] = 0x80;
-		chip8.memory[0x4A] = 0xF0;
-
-		chip8.memory[0x4B] = 0xF0;
-		chip8.memory[0x4C] = 0x80;
-		chip8.memory[0x4D] = 0xF0;
-		chip8.memory[0x4E] = 0x80;
-		chip8.memory[0x4F] = 0x80;
-
+		chip8.reset();
 		chip8
 	}
 
-	fn load_rom(&mut self, filename: String) {
-		let mut file = File::open(filename).expect("file not found");
-		let mut buffer: Vec<u8> = Vec::new();
-		file.read_to_end(&mut buffer).expect("something went wrong reading the file");
-		self.memory[ROM_START_ADDRESS as usize..ROM_START_ADDRESS as usize + buffer.len()].copy_from_slice(&buffer);
+	pub fn headless() -> Chip8 {
+		Chip8::new(Box::new(Headless))
+	}
+
+	// also resets the machine
+	pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+		if ROM_START_ADDRESS as usize + rom.len() > MEMORY_SIZE {
+			return Err(format!("ROM too large: {} bytes, at most {} fit in memory", rom.len(), MEMORY_SIZE - ROM_START_ADDRESS as usize));
+		}
+		self.rom = rom.to_vec();
+		self.reset();
+		Ok(())
+	}
+
+	// back to the power-on state with the loaded ROM, the settings and the keys are kept
+	pub fn reset(&mut self) {
+		self.memory = [0; MEMORY_SIZE];
+		self.memory[..FONT.len()].copy_from_slice(&FONT);
+		let rom_start = ROM_START_ADDRESS as usize;
+		self.memory[rom_start..rom_start + self.rom.len()].copy_from_slice(&self.rom);
+
+		self.stack = [0; STACK_SIZE];
+		self.register_v = [0; V_REGISTERS_COUNT];
+		self.register_i = 0;
+		self.register_dt = 0;
+		self.register_st = 0;
+		self.register_pc = ROM_START_ADDRESS;
+		self.register_sp = 0xFF;
+		self.display = Display::new();
+		self.random = Random::new(self.seed);
+		self.frame_cycles = 0;
+		self.skipped_cycles = 0;
+		self.pressed_key = None;
+
+		// the decoded and recompiled code is stale
+		self.threaded_interpreter = None;
+		self.recompiler = None;
+	}
+
+	pub fn rom_hash(&self) -> u64 {
+		rom_hash(&self.rom)
 	}
 
+	// the whole machine but the keys, in the layout of the current save state version
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut writer = StateWriter::new();
+		writer.write_u64(self.rom_hash());
+		writer.write_u8(self.quirks.shift_vx as u8 | (self.quirks.load_store_keep_i as u8) << 1 | (self.quirks.jump_vx as u8) << 2);
+		writer.write_u32(self.cycles_per_frame);
+
+		writer.write_bytes(&self.memory);
+		for &address in self.stack.iter() {
+			writer.write_u16(address);
+		}
+		writer.write_bytes(&self.register_v);
+		writer.write_u16(self.register_i);
+		writer.write_u8(self.register_dt);
+		writer.write_u8(self.register_st);
+		writer.write_u16(self.register_pc);
+		writer.write_u8(self.register_sp);
+		for &row in self.display.rows() {
+			writer.write_u64(row);
+		}
+
+		writer.write_u8(self.pressed_key.unwrap_or(0xFF));
+		writer.write_u32(self.frame_cycles);
+		writer.write_u64(self.frame_count);
+		writer.write_u64(self.cycle_count);
+		// version 2
+		writer.write_u64(self.seed);
+		writer.write_u64(self.random.state());
+		writer.bytes
+	}
+
+	// the machine is left untouched when the state can't be loaded
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let mut reader = StateReader::new(state)?;
+		if reader.read_u64()? != self.rom_hash() {
+			return Err("the save state is for another ROM".to_string());
+		}
+		let quirks = reader.read_u8()?;
+		let cycles_per_frame = reader.read_u32()?;
+
+		let mut memory = [0; MEMORY_SIZE];
+		memory.copy_from_slice(reader.read_bytes(MEMORY_SIZE)?);
+		let mut stack = [0; STACK_SIZE];
+		for address in stack.iter_mut() {
+			*address = reader.read_u16()?;
+		}
+		let mut register_v = [0; V_REGISTERS_COUNT];
+		register_v.copy_from_slice(reader.read_bytes(V_REGISTERS_COUNT)?);
+		let register_i = reader.read_u16()?;
+		let register_dt = reader.read_u8()?;
+		let register_st = reader.read_u8()?;
+		let register_pc = reader.read_u16()?;
+		let register_sp = reader.read_u8()?;
+		let mut rows = [0; DISPLAY_HEIGHT];
+		for row in rows.iter_mut() {
+			*row = reader.read_u64()?;
+		}
+
+		let pressed_key = reader.read_u8()?;
+		let frame_cycles = reader.read_u32()?;
+		let frame_count = reader.read_u64()?;
+		let cycle_count = reader.read_u64()?;
+		// version 1 states don't have the generator, it's kept as is
+		let random = if reader.version >= 2 {
+			let seed = reader.read_u64()?;
+			let random = Random::from_state(reader.read_u64()?).ok_or_else(|| "corrupted save state".to_string())?;
+			Some((seed, random))
+		} else {
+			None
+		};
+		reader.finish()?;
+
+		// 00EE would return outside of the memory and FX65 read past its end
+		let stack_outside = stack.iter().any(|&address| address as usize >= MEMORY_SIZE - 1);
+		if register_pc as usize >= MEMORY_SIZE - 1 || (register_sp != 0xFF && register_sp as usize >= STACK_SIZE) || stack_outside || register_i as usize >= MEMORY_SIZE || cycles_per_frame == 0 {
+			return Err("corrupted save state".to_string());
+		}
+
+		self.quirks.shift_vx = quirks & 1 != 0;
+		self.quirks.load_store_keep_i = quirks & 2 != 0;
+		self.quirks.jump_vx = quirks & 4 != 0;
+		self.cycles_per_frame = cycles_per_frame;
+		let previous_memory = mem::replace(&mut self.memory, memory);
+		self.stack = stack;
+		self.register_v = register_v;
+		self.register_i = register_i;
+		self.register_dt = register_dt;
+		self.register_st = register_st;
+		self.register_pc = register_pc;
+		self.register_sp = register_sp;
+		self.display = Display::from_rows(rows);
+		self.pressed_key = if pressed_key < 0x10 { Some(pressed_key) } else { None };
+		self.frame_cycles = frame_cycles;
+		self.skipped_cycles = 0;
+		self.frame_count = frame_count;
+		self.cycle_count = cycle_count;
+		if let Some((seed, random)) = random {
+			self.seed = seed;
+			self.random = random;
+		}
+
+		// the memory may hold other code than what was decoded and recompiled, rewinding
+		// loads a state every frame so the recompiler only drops the blocks that changed
+		self.threaded_interpreter = None;
+		if let Some(ref mut recompiler) = self.recompiler {
+			recompiler.invalidate_changed_code(&previous_memory, &self.memory);
+		}
+		Ok(())
+	}
+
+	pub fn save_state_file(&self, path: &Path) -> Result<(), String> {
+		File::create(path).and_then(|mut file| file.write_all(&self.save_state()))
+			.map_err(|error| format!("can't save state to '{}': {}", path.display(), error))
+	}
+
+	pub fn load_state_file(&mut self, path: &Path) -> Result<(), String> {
+		let mut state = Vec::new();
+		File::open(path).and_then(|mut file| file.read_to_end(&mut state))
+			.map_err(|error| format!("can't read save state '{}': {}", path.display(), error))?;
+		self.load_state(&state).map_err(|error| format!("can't load '{}': {}", path.display(), error))
+	}
+
+	// FNV-1a of the save state, equal when replays are in sync
+	pub fn state_hash(&self) -> u64 {
+		rom_hash(&self.save_state())
+	}
+
+	// resets the machine and records the keys from there, state hashes of every frame too if asked
+	pub fn record_movie(&mut self, record_hashes: bool) {
+		let mut movie = Movie::new(self.rom_hash(), self.engine, self.quirks, self.cycles_per_frame, self.seed);
+		movie.start_recording(record_hashes);
+		self.start_movie(movie);
+	}
+
+	// resets the machine with the movie's settings and presses its keys from there
+	pub fn play_movie(&mut self, mut movie: Movie) -> Result<(), String> {
+		match movie.rom_hash {
+			Some(rom_hash) if rom_hash != self.rom_hash() => return Err("the movie was recorded with another ROM".to_string()),
+			_ => ()
+		}
+		match movie.engine {
+			Some(engine) if engine != self.engine => return Err(format!("the movie was recorded with the {} engine", engine)),
+			_ => ()
+		}
+		self.quirks = movie.quirks;
+		self.cycles_per_frame = movie.cycles_per_frame;
+		self.seed = movie.seed;
+		movie.start_playing();
+		self.start_movie(movie);
+		Ok(())
+	}
+
+	fn start_movie(&mut self, movie: Movie) {
+		self.reset();
+		self.keyboard = Keyboard::new();
+		self.frame_count = 0;
+		self.cycle_count = 0;
+		self.movie = Some(movie);
+		self.movie_frame();
+	}
+
+	pub fn movie(&self) -> Option<&Movie> {
+		self.movie.as_ref()
+	}
+
+	// the recorded movie, complete up to the current frame
+	pub fn stop_movie(&mut self) -> Option<Movie> {
+		self.movie.take()
+	}
+
+	fn is_movie_running(&self) -> bool {
+		self.movie.as_ref().is_some_and(|movie| !movie.is_finished(self.frame_count))
+	}
+
+	fn movie_frame(&mut self) {
+		if let Some(mut movie) = self.movie.take() {
+			let hash = if movie.needs_hash(self.frame_count) { Some(self.state_hash()) } else { None };
+			movie.frame(self.frame_count, &mut self.keyboard, hash);
+			self.movie = Some(movie);
+		}
+	}
+
+	// <ROM hash>-<slot>.state in the state directory
+	pub fn state_slot_path(&self, slot: u8) -> Option<PathBuf> {
+		self.state_directory.as_ref().map(|directory| directory.join(format!("{:016x}-{}.state", self.rom_hash(), slot)))
+	}
+
+	fn save_state_slot(&self, slot: u8) -> Result<(), String> {
+		let path = self.state_slot_path(slot).ok_or_else(|| "no directory for save states".to_string())?;
+		if let Some(directory) = path.parent() {
+			fs::create_dir_all(directory).map_err(|error| format!("can't create '{}': {}", directory.display(), error))?;
+		}
+		self.save_state_file(&path)
+	}
+
+	fn load_state_slot(&mut self, slot: u8) -> Result<(), String> {
+		let path = self.state_slot_path(slot).ok_or_else(|| "no directory for save states".to_string())?;
+		if !path.is_file() {
+			return Err(format!("slot {} is empty", slot));
+		}
+		self.load_state_file(&path)
+	}
+
+	pub fn set_key_state(&mut self, key: u8, pressed: bool) {
+		self.keyboard.set_key_state(key, pressed);
+	}
+
+	// one byte per pixel, 1 when lit
+	pub fn frame_buffer(&mut self) -> &[u8] {
+		self.display.frame_buffer()
+	}
+
+	pub fn sound_timer(&self) -> u8 {
+		self.register_st
+	}
+
+	pub fn is_sound_playing(&self) -> bool {
+		self.register_st > 0
+	}
+
+	pub fn frame_count(&self) -> u64 {
+		self.frame_count
+	}
+
+	// instructions executed since the start, the recompiler counts skipped ones too
+	pub fn cycle_count(&self) -> u64 {
+		self.cycle_count
+	}
+
+	// stops run() and the current run_cycles() at the end of the frame
+	pub fn quit(&mut self) {
+		self.quit = true;
+		self.remaining_cycles = 0;
+	}
+
+	pub fn has_quit(&self) -> bool {
+		self.quit
+	}
+
+	// returns true when the memory was edited from the debugger
 	#[cfg(feature="debugger")]
-	fn print_registers(&self) {
-		println!("PC= {:x}", self.register_pc);
-		println!("I= {:x}", self.register_i);
-		println!("DT= {:x}", self.register_dt);
-		println!("ST= {:x}", self.register_st);
-		println!("SP= {:x}", self.register_sp);
-		for i in 0..16 as usize {
-			println!("V{}= {:x}", i, self.register_v[i]);
+	fn debug(&mut self) -> bool {
+		match self.debugger.take() {
+			Some(mut debugger) => {
+				let memory_edited = debugger.before_instruction(self);
+				self.debugger = Some(debugger);
+				memory_edited
+			},
+			None => false
 		}
-		let _ = Command::new("cmd.exe").arg("/c").arg("pause").status();
 	}
 
-	pub extern "stdcall" fn refresh(&mut self) {
-		// ~60Hz
-		if self.time_last_frame.elapsed() >= Duration::from_millis(1000 / 60) { 
-			self.time_last_frame = Instant::now();
-			self.keyboard.update_key_states();
-			self.display.refresh();
+	fn trace_instruction(&mut self) {
+		let pc = self.register_pc as usize;
+		let line = format!("{:03X}: {:02X}{:02X}  V={:02X?} I={:03X} DT={:02X} ST={:02X} SP={:02X}",
+			pc, self.memory[pc], self.memory[pc + 1], self.register_v, self.register_i, self.register_dt, self.register_st, self.register_sp);
+		if let Some(ref mut trace) = self.trace {
+			let _ = writeln!(trace, "{}", line);
+		}
+	}
+
+	// accounts for executed instructions, returns false once the cycles to run are spent
+	pub extern "stdcall" fn refresh(&mut self, cycles: u32) -> bool {
+		let cycles = cycles.saturating_sub(mem::replace(&mut self.skipped_cycles, 0));
+		self.frame_cycles += cycles;
+		self.cycle_count += cycles as u64;
+		self.remaining_cycles = self.remaining_cycles.saturating_sub(cycles);
+
+		// recompiled blocks may span several frames, the cycles past the end of one count in the next
+		while self.frame_cycles >= self.cycles_per_frame {
+			self.frame_cycles -= self.cycles_per_frame;
+			self.frame_count += 1;
+			self.frontend.update_key_states(&mut self.keyboard);
+			self.movie_frame();
+			if self.frontend.is_closed() {
+				self.quit();
+			}
+			self.frontend.present(&self.display);
 			if self.register_dt > 0 {
 				self.register_dt -= 1
 			}
@@ -186,26 +520,322 @@ impl Chip8 {
 				// TODO: beep
 			}
 		}
+
+		self.remaining_cycles > 0
+	}
+
+	// CXNN
+	pub extern "stdcall" fn random_byte(&mut self) -> u8 {
+		self.random.next_byte()
 	}
 
-	pub fn run(&mut self, filename: String) {
-		self.load_rom(filename);
+	// FX0A completes when a key is released like on the VIP, returns false while waiting
+	// so that the engines execute it again on the next frame
+	pub extern "stdcall" fn wait_key_release(&mut self, x: u32) -> bool {
+		match self.pressed_key {
+			Some(key) if !self.keyboard.is_pressed(key) => {
+				self.register_v[x as usize] = key;
+				self.pressed_key = None;
+				true
+			},
+			Some(_) => false,
+			None => {
+				self.pressed_key = self.keyboard.pressed_key();
+				false
+			}
+		}
+	}
 
-		#[cfg(not(feature="interpreter"))]
-		let mut recompiler = Recompiler::new(&self.register_pc);
-		
-		loop {
-			#[cfg(feature="debugger")]
-			self.print_registers();
+	// FX07, 3X00, 1NNN jumping back to FX07: the ROM is waiting for the delay timer
+	pub fn is_idle_loop(&self, address: u16) -> bool {
+		let address = address as usize;
+		if address + 6 > MEMORY_SIZE {
+			return false;
+		}
+
+		let x = self.memory[address] & 0x0F;
+		self.memory[address..address + 6] == [0xF0 | x, 0x07, 0x30 | x, 0x00, 0x10 | (address >> 8) as u8, address as u8]
+	}
+
+	// fast-forwards to the next timer tick instead of spinning in an idle loop,
+	// only when the caller runs the rest of the frame anyway, not for a single step
+	pub extern "stdcall" fn wait_next_frame(&mut self, cycles: u32) -> bool {
+		if self.register_dt > 0 && self.remaining_cycles >= self.cycles_per_frame.saturating_sub(self.frame_cycles) {
+			self.skip_to_next_frame(cycles)
+		} else {
+			self.refresh(cycles)
+		}
+	}
+
+	// the keys and timers only change at the end of the frame, the cycles are counted first
+	pub extern "stdcall" fn skip_to_next_frame(&mut self, cycles: u32) -> bool {
+		self.refresh(cycles);
+		let cycles_to_next_frame = self.cycles_per_frame - self.frame_cycles;
+		self.refresh(cycles_to_next_frame)
+	}
+
+	// one instruction with every engine, the recompiler compiles a block of a single instruction for it
+	pub fn step(&mut self) {
+		match self.engine {
+			Engine::Recompiler => {
+				self.remaining_cycles = 1;
+				let mut recompiler = self.take_recompiler();
+				recompiler.execute_next_instruction(self);
+				self.recompiler = Some(recompiler);
+			},
+			_ => self.run_cycles(1)
+		}
+	}
+
+	pub fn run_frame(&mut self) {
+		let cycles = self.cycles_per_frame.saturating_sub(self.frame_cycles);
+		self.run_cycles(cmp::max(cycles, 1));
+	}
+
+	// the recompiler only stops at jumps, so it may run a few more instructions
+	pub fn run_cycles(&mut self, cycles: u32) {
+		self.remaining_cycles = cycles;
+
+		match self.engine {
+			Engine::Interpreter => {
+				while self.remaining_cycles > 0 {
+					#[cfg(feature="debugger")]
+					self.debug();
+
+					if self.trace.is_some() {
+						self.trace_instruction();
+					}
+					Interpreter::execute_next_instruction(self);
+					self.refresh(1);
+				}
+			},
+			Engine::Threaded => {
+				let mut threaded_interpreter = self.threaded_interpreter.take().unwrap_or_else(|| Box::new(ThreadedInterpreter::new()));
+				while self.remaining_cycles > 0 {
+					// edited code must be decoded again
+					#[cfg(feature="debugger")]
+					{
+						if self.debug() {
+							threaded_interpreter = Box::new(ThreadedInterpreter::new());
+						}
+					}
+
+					if self.trace.is_some() {
+						self.trace_instruction();
+					}
+					threaded_interpreter.execute_next_instruction(self);
+					self.refresh(1);
+				}
+				self.threaded_interpreter = Some(threaded_interpreter);
+			},
+			Engine::Recompiler => {
+				let mut recompiler = self.take_recompiler();
+				while self.remaining_cycles > 0 {
+					// blocks end where the debugger may stop next, they're recompiled when that changes
+					#[cfg(feature="debugger")]
+					{
+						if self.debug() {
+							recompiler.invalidate();
+						}
+						if let Some(ref debugger) = self.debugger {
+							if recompiler.stops_generation() != Some(debugger.generation()) {
+								recompiler.set_stops(debugger.stop_addresses(), debugger.generation());
+							}
+							if debugger.is_stepping() {
+								recompiler.execute_next_instruction(self);
+								continue;
+							}
+						}
+					}
+
+					recompiler.execute_next_code_block(self);
+				}
+				self.recompiler = Some(recompiler);
+			}
+		}
+	}
+
+	// recompiled code points into this Chip8, it's thrown away if the Chip8 moved
+	fn take_recompiler(&mut self) -> Box<Recompiler> {
+		match self.recompiler.take() {
+			Some(recompiler) if recompiler.is_compiled_for(self) => recompiler,
+			_ => Box::new(Recompiler::new(self))
+		}
+	}
+
+	// between frames, when no instruction runs
+	fn poll_frontend(&mut self) {
+		// movies only see the keys at the end of frames
+		let mut keyboard = self.keyboard;
+		self.frontend.update_key_states(&mut keyboard);
+		if !self.is_movie_running() {
+			self.keyboard = keyboard;
+		}
+		if self.frontend.is_closed() {
+			self.quit();
+		}
+		self.frontend.present(&self.display);
+	}
+
+	// runs in real time at ~60 frames per second until the frontend is closed
+	pub fn run(&mut self) {
+		let mut paused = false;
+		let mut fast_forward = false;
+		let mut slow_motion = false;
+		let mut rewinding = false;
+
+		while !self.quit {
+			let mut step = false;
+			while let Some(action) = self.frontend.next_action() {
+				match action {
+					// the movie would no longer match the machine
+					Action::Reset | Action::SaveState(_) | Action::LoadState(_) | Action::Rewind(true) if self.is_movie_running() => {
+						eprintln!("not while a movie is recorded or played");
+					},
+					Action::TogglePause => paused = !paused,
+					Action::StepFrame => {
+						paused = true;
+						step = true;
+					},
+					Action::Reset => self.reset(),
+					Action::FastForward(enabled) => fast_forward = enabled,
+					Action::ToggleSlowMotion => slow_motion = !slow_motion,
+					Action::SaveState(slot) => match self.save_state_slot(slot) {
+						Ok(()) => println!("saved state {}", slot),
+						Err(error) => eprintln!("{}", error)
+					},
+					Action::LoadState(slot) => match self.load_state_slot(slot) {
+						Ok(()) => println!("loaded state {}", slot),
+						Err(error) => eprintln!("{}", error)
+					},
+					Action::Rewind(enabled) => rewinding = enabled
+				}
+			}
+
+			let snapshot = self.rewind.as_mut().and_then(|rewind| if rewinding { rewind.step_back() } else { None });
+			if let Some(state) = snapshot {
+				// running resumes from the last snapshot shown
+				if let Err(error) = self.load_state(&state) {
+					eprintln!("can't rewind: {}", error);
+				}
+				self.poll_frontend();
+			} else if !paused || step {
+				self.run_frame();
+				if let Some(mut rewind) = self.rewind.take() {
+					if rewind.is_snapshot_due() {
+						rewind.push(self.save_state());
+					}
+					self.rewind = Some(rewind);
+				}
+			} else {
+				// the frontend still handles its events while paused
+				self.poll_frontend();
+			}
 
-			#[cfg(feature="interpreter")]
-			Interpreter::execute_next_instruction(self);
+			let frame_duration = Duration::from_millis(if slow_motion { 4000 / 60 } else { 1000 / 60 });
+			let elapsed = self.time_last_frame.elapsed();
+			if !fast_forward && elapsed < frame_duration {
+				thread::sleep(frame_duration - elapsed);
+			}
+			self.time_last_frame = Instant::now();
+		}
+	}
+}
 
-			#[cfg(feature="interpreter")]
-			self.refresh();
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// offsets in a save state: header, ROM hash, quirks and ipf, then the memory
+	const STACK_OFFSET: usize = 6 + 8 + 1 + 4 + MEMORY_SIZE;
+	const REGISTER_I_OFFSET: usize = STACK_OFFSET + STACK_SIZE * 2 + V_REGISTERS_COUNT;
+
+	#[test]
+	fn load_state_rejects_addresses_outside_memory() {
+		let mut chip8 = Chip8::headless();
+		chip8.load_rom(&[0x12, 0x00]).unwrap();
+		chip8.register_i = 0xFFF;
+		chip8.stack[0] = 0xFFC;
+		let state = chip8.save_state();
+		assert!(chip8.load_state(&state).is_ok());
+
+		let mut corrupted = state.clone();
+		corrupted[STACK_OFFSET + 2..STACK_OFFSET + 4].copy_from_slice(&[0xFF, 0x0F]);
+		assert_eq!(chip8.load_state(&corrupted).unwrap_err(), "corrupted save state");
+
+		let mut corrupted = state.clone();
+		corrupted[REGISTER_I_OFFSET..REGISTER_I_OFFSET + 2].copy_from_slice(&[0x00, 0x10]);
+		assert_eq!(chip8.load_state(&corrupted).unwrap_err(), "corrupted save state");
+		assert_eq!(chip8.register_i, 0xFFF);
+	}
+
+	#[test]
+	fn idle_loops_only_fast_forward_whole_frames() {
+		for &engine in &[Engine::Interpreter, Engine::Threaded] {
+			let mut chip8 = Chip8::headless();
+			chip8.engine = engine;
+			chip8.load_rom(&[0xF0, 0x07, 0x30, 0x00, 0x12, 0x00]).unwrap();
+			chip8.register_dt = 5;
+			for _ in 0..6 {
+				chip8.step();
+			}
+			assert_eq!((chip8.frame_count(), chip8.cycle_count(), chip8.register_dt), (0, 6, 5));
 
-			#[cfg(not(feature="interpreter"))]
-			recompiler.execute_next_code_block(self);
+			chip8.run_frame();
+			assert_eq!((chip8.frame_count(), chip8.register_dt), (1, 4));
 		}
 	}
+
+	// waits for a key, draws its digit at a random height and starts over
+	const KEY_ROM: [u8; 10] = [0xF0, 0x0A, 0xF0, 0x29, 0xC1, 0x1F, 0xD0, 0x15, 0x12, 0x00];
+	const KEY_MOVIE: &str = "
+		engine = interpreter
+		seed = 7
+		3 = 5
+		5 = -
+		8 = A
+		9 = -
+		hash 4 = 36cd52e028ca249a
+		hash 9 = ace4a6db4ac9ce00
+		hash 12 = 29ecebd97e8238af
+	";
+
+	fn play_key_movie(movie: &str) -> Chip8 {
+		let mut chip8 = Chip8::headless();
+		chip8.engine = Engine::Interpreter;
+		chip8.load_rom(&KEY_ROM).unwrap();
+		let movie = Movie::parse(movie, DEFAULT_CYCLES_PER_FRAME).unwrap();
+		assert_eq!(movie.length, Some(13));
+		chip8.play_movie(movie).unwrap();
+		while !chip8.movie().unwrap().is_finished(chip8.frame_count()) {
+			chip8.run_frame();
+		}
+		chip8
+	}
+
+	#[test]
+	fn movie_replays_in_sync() {
+		let chip8 = play_key_movie(KEY_MOVIE);
+		assert_eq!(chip8.frame_count(), 13);
+		assert_eq!(chip8.seed, 7);
+		assert_eq!(chip8.movie().unwrap().desync(), None);
+	}
+
+	#[test]
+	fn movie_detects_desyncs() {
+		let chip8 = play_key_movie(&KEY_MOVIE.replace("8 = A", "8 = B"));
+		assert_eq!(chip8.movie().unwrap().desync(), Some(9));
+	}
+
+	#[test]
+	fn movie_needs_its_engine() {
+		let mut chip8 = Chip8::headless();
+		chip8.engine = Engine::Threaded;
+		chip8.load_rom(&KEY_ROM).unwrap();
+		let movie = Movie::parse(KEY_MOVIE, DEFAULT_CYCLES_PER_FRAME).unwrap();
+		assert_eq!(chip8.play_movie(movie).unwrap_err(), "the movie was recorded with the interpreter engine");
+
+		let movie = Movie::parse(&KEY_MOVIE.replace("engine = interpreter", ""), DEFAULT_CYCLES_PER_FRAME).unwrap();
+		assert!(chip8.play_movie(movie).is_ok());
+	}
 }
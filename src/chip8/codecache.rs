@@ -8,20 +8,25 @@ use chip8::MEMORY_SIZE;
 use chip8::ROM_START_ADDRESS;
 use chip8::codeemitter::CodeEmitter;
 
-const CACHE_CAPACITY: usize = 0x10000;
+const CACHE_CAPACITY: usize = 0x100000;
 
 pub struct CodeCache {
 	pub x86_block_addresses: [u32; MEMORY_SIZE],
+	// stubs setting PC and returning, for jumps that must leave the code cache
+	pub stub_addresses: [u32; MEMORY_SIZE],
 	cache: Mmap,
-	cache_size: usize
+	cache_size: usize,
+	stubs_size: usize
 }
 
 impl CodeCache {
 	pub fn new(register_pc: &u16) -> CodeCache {
 		let mut code_cache = CodeCache {
 			x86_block_addresses: [0; MEMORY_SIZE],
+			stub_addresses: [0; MEMORY_SIZE],
 			cache: Mmap::anonymous(CACHE_CAPACITY, Protection::ReadWrite).unwrap(),
-			cache_size: 0
+			cache_size: 0,
+			stubs_size: 0
 		};
 
 		for address in ROM_START_ADDRESS..MEMORY_SIZE as u16 {
@@ -30,26 +35,48 @@ impl CodeCache {
 			code_emitter.ret();
 			code_cache.insert(address, code_emitter.raw_code);
 		}
+		code_cache.stub_addresses = code_cache.x86_block_addresses;
+		code_cache.stubs_size = code_cache.cache_size;
 
 		code_cache
 	}
 
+	pub fn has_room(&self, size: usize) -> bool {
+		self.cache_size + size <= CACHE_CAPACITY
+	}
+
+	// forgets every block but the stubs
+	pub fn clear(&mut self) {
+		self.x86_block_addresses = self.stub_addresses;
+		self.cache_size = self.stubs_size;
+	}
+
 	pub fn insert(&mut self, address: u16, block: Vec<u8>) {
+		self.x86_block_addresses[address as usize] = self.append(block);
+	}
+
+	// adds a block without making it the target of jumps, returns where it starts
+	pub fn append(&mut self, block: Vec<u8>) -> u32 {
 		let new_size = self.cache_size + block.len();
 		if new_size > CACHE_CAPACITY {
 			panic!("Cache overflow");
 		}
 		let _ = self.cache.set_protection(Protection::ReadWrite);
-		unsafe { 
+		let x86_address = unsafe { 
 			self.cache.as_mut_slice()[self.cache_size..new_size].copy_from_slice(&block);
-			self.x86_block_addresses[address as usize] = self.cache.ptr().offset(self.cache_size as isize) as u32;
-		}
+			self.cache.ptr().offset(self.cache_size as isize) as u32
+		};
 		let _ = self.cache.set_protection(Protection::ReadExecute);
 		self.cache_size = new_size;
+		x86_address
 	}
 
 	pub fn execute(&self, address: u16) {
-		let f: fn() = unsafe { mem::transmute(self.x86_block_addresses[address as usize]) };
+		self.execute_at(self.x86_block_addresses[address as usize]);
+	}
+
+	pub fn execute_at(&self, x86_address: u32) {
+		let f: fn() = unsafe { mem::transmute(x86_address) };
 		f();
 	}
 }
@@ -25,6 +25,23 @@ impl CodeEmitter {
 		self.push_u8((value >> 24) as u8);
 	}
 
+	// SSE2 instructions on two XMM registers, given by number
+	fn push_xmm_xmm(&mut self, opcode: u8, destination: u8, source: u8) {
+		self.push_u8(0x66);
+		self.push_u8(0x0F);
+		self.push_u8(opcode);
+		self.push_u8(0xC0 | destination << 3 | source);
+	}
+
+	// makes a forward branch land on the next emitted instruction
+	pub fn patch_forward(&mut self, position: usize) {
+		let disp = (self.raw_code.len() - position - 4) as u32;
+		self.raw_code[position] = disp as u8;
+		self.raw_code[position + 1] = (disp >> 8) as u8;
+		self.raw_code[position + 2] = (disp >> 16) as u8;
+		self.raw_code[position + 3] = (disp >> 24) as u8;
+	}
+
 	pub fn add_al_to_al(&mut self) {
 		self.push_u8(0x00);
 		self.push_u8(0xC0);
@@ -61,6 +78,13 @@ impl CodeEmitter {
 		self.push_u8(imm);
 	}
 
+	pub fn add_imm_to_m32(&mut self, imm: u32, m: &u32) {
+		self.push_u8(0x81);
+		self.push_u8(0x05);
+		self.push_u32(m as *const u32 as u32);
+		self.push_u32(imm);
+	}
+
 	pub fn add_imm_to_m16(&mut self, imm: u16, m: &u16) {
 		self.push_u8(0x66);
 		self.push_u8(0x81);
@@ -80,6 +104,11 @@ impl CodeEmitter {
 		self.push_u8(imm);
 	}
 
+	pub fn and_imm_to_eax(&mut self, imm: u32) {
+		self.push_u8(0x25);
+		self.push_u32(imm);
+	}
+
 	pub fn and_m_al(&mut self, m: &u8) {
 		self.push_u8(0x20);
 		self.push_u8(0x05);
@@ -108,6 +137,19 @@ impl CodeEmitter {
 		self.push_u32(m as *const u8 as u32);
 	}
 
+	pub fn cmp_imm_with_ecx(&mut self, imm: u32) {
+		self.push_u8(0x81);
+		self.push_u8(0xF9);
+		self.push_u32(imm);
+	}
+
+	pub fn cmp_imm_with_m32(&mut self, imm: u32, m: &u32) {
+		self.push_u8(0x81);
+		self.push_u8(0x3D);
+		self.push_u32(m as *const u32 as u32);
+		self.push_u32(imm);
+	}
+
 	pub fn cmp_imm_with_m8(&mut self, imm: u8, m: &u8) {
 		self.push_u8(0x80);
 		self.push_u8(0x3D);
@@ -120,14 +162,27 @@ impl CodeEmitter {
 		self.push_u8(0xF2);
 	}
 
-	pub fn je(&mut self, disp: i8) {
-		self.push_u8(0x74);
-		self.push_u8(disp as u8);
+	// je rel32, returns the position of the displacement to patch
+	pub fn je_forward(&mut self) -> usize {
+		self.push_u8(0x0F);
+		self.push_u8(0x84);
+		self.push_u32(0);
+		self.raw_code.len() - 4
 	}
 
-	pub fn jne(&mut self, disp: i8) {
-		self.push_u8(0x75);
-		self.push_u8(disp as u8);
+	// jne rel32, returns the position of the displacement to patch
+	pub fn jne_forward(&mut self) -> usize {
+		self.push_u8(0x0F);
+		self.push_u8(0x85);
+		self.push_u32(0);
+		self.raw_code.len() - 4
+	}
+
+	// jmp rel32, returns the position of the displacement to patch
+	pub fn jmp_forward(&mut self) -> usize {
+		self.push_u8(0xE9);
+		self.push_u32(0);
+		self.raw_code.len() - 4
 	}
 
 	pub fn jmp_eax(&mut self) {
@@ -135,6 +190,13 @@ impl CodeEmitter {
 		self.push_u8(0xE0);
 	}
 
+	// lea eax,[ecx+displacement]
+	pub fn lea_eax_ecx(&mut self, displacement: u32) {
+		self.push_u8(0x8D);
+		self.push_u8(0x81);
+		self.push_u32(displacement);
+	}
+
 	pub fn mov_al_to_cl(&mut self) {
 		self.push_u8(0x88);
 		self.push_u8(0xC1);
@@ -160,6 +222,13 @@ impl CodeEmitter {
 		self.push_u32(imm);
 	}
 
+	pub fn mov_cx_to_m(&mut self, m: &u16) {
+		self.push_u8(0x66);
+		self.push_u8(0x89);
+		self.push_u8(0x0D);
+		self.push_u32(m as *const u16 as u32);
+	}
+
 	pub fn mov_cl_to_m(&mut self, m: &u8) {
 		self.push_u8(0x88);
 		self.push_u8(0x0D);
@@ -239,6 +308,42 @@ impl CodeEmitter {
 		self.push_u16(imm);
 	}
 
+	pub fn movd_eax_to_xmm(&mut self, xmm: u8) {
+		self.push_xmm_xmm(0x6E, xmm, 0);
+	}
+
+	pub fn movd_xmm_to_eax(&mut self, xmm: u8) {
+		self.push_xmm_xmm(0x7E, xmm, 0);
+	}
+
+	pub fn movd_xmm_to_ecx(&mut self, xmm: u8) {
+		self.push_xmm_xmm(0x7E, xmm, 1);
+	}
+
+	pub fn movdqa_xmm_to_xmm(&mut self, destination: u8, source: u8) {
+		self.push_xmm_xmm(0x6F, destination, source);
+	}
+
+	// movq xmm,qword ptr [base+8*eax]
+	pub fn movq_m_eax8_to_xmm(&mut self, xmm: u8, base: u32) {
+		self.push_u8(0xF3);
+		self.push_u8(0x0F);
+		self.push_u8(0x7E);
+		self.push_u8(0x04 | xmm << 3);
+		self.push_u8(0xC5);
+		self.push_u32(base);
+	}
+
+	// movq qword ptr [base+8*eax],xmm
+	pub fn movq_xmm_to_m_eax8(&mut self, xmm: u8, base: u32) {
+		self.push_u8(0x66);
+		self.push_u8(0x0F);
+		self.push_u8(0xD6);
+		self.push_u8(0x04 | xmm << 3);
+		self.push_u8(0xC5);
+		self.push_u32(base);
+	}
+
 	pub fn movzx_ah_to_ax(&mut self) {
 		self.push_u8(0x66);
 		self.push_u8(0x0F);
@@ -261,13 +366,6 @@ impl CodeEmitter {
 		self.push_u32(m as *const u8 as u32);
 	}
 
-	pub fn movzx_m16_to_eax(&mut self, m: &u16) {
-		self.push_u8(0x0F);
-		self.push_u8(0xB7);
-		self.push_u8(0x05);
-		self.push_u32(m as *const u16 as u32);
-	}
-
 	pub fn movzx_m_to_cx(&mut self, m: &u8) {
 		self.push_u8(0x66);
 		self.push_u8(0x0F);
@@ -290,6 +388,21 @@ impl CodeEmitter {
 		self.push_u32(m as *const u16 as u32);
 	}
 
+	pub fn movzx_m16_to_edx(&mut self, m: &u16) {
+		self.push_u8(0x0F);
+		self.push_u8(0xB7);
+		self.push_u8(0x15);
+		self.push_u32(m as *const u16 as u32);
+	}
+
+	// movzx eax,byte ptr [edx+displacement]
+	pub fn movzx_m8_to_eax_edx(&mut self, displacement: u32) {
+		self.push_u8(0x0F);
+		self.push_u8(0xB6);
+		self.push_u8(0x82);
+		self.push_u32(displacement);
+	}
+
 	// movzx ecx,word ptr [edi+2*ecx]
 	pub fn movzx_m16_to_ecx_edi2ecx(&mut self) {
 		self.push_u8(0x0F);
@@ -304,12 +417,53 @@ impl CodeEmitter {
 		self.push_u32(m as *const u8 as u32);
 	}
 
+	pub fn neg_eax(&mut self) {
+		self.push_u8(0xF7);
+		self.push_u8(0xD8);
+	}
+
+	pub fn or_ecx_to_eax(&mut self) {
+		self.push_u8(0x09);
+		self.push_u8(0xC8);
+	}
+
 	pub fn or_m_al(&mut self, m: &u8) {
 		self.push_u8(0x08);
 		self.push_u8(0x05);
 		self.push_u32(m as *const u8 as u32);
 	}
 
+	pub fn pand(&mut self, destination: u8, source: u8) {
+		self.push_xmm_xmm(0xDB, destination, source);
+	}
+
+	pub fn por(&mut self, destination: u8, source: u8) {
+		self.push_xmm_xmm(0xEB, destination, source);
+	}
+
+	// shifts by the count in the low quadword of the source, 64 or more clears the destination
+	pub fn psllq(&mut self, destination: u8, count: u8) {
+		self.push_xmm_xmm(0xF3, destination, count);
+	}
+
+	pub fn psllq_imm(&mut self, xmm: u8, imm: u8) {
+		self.push_xmm_xmm(0x73, 6, xmm);
+		self.push_u8(imm);
+	}
+
+	pub fn psrlq(&mut self, destination: u8, count: u8) {
+		self.push_xmm_xmm(0xD3, destination, count);
+	}
+
+	pub fn psrlq_imm(&mut self, xmm: u8, imm: u8) {
+		self.push_xmm_xmm(0x73, 2, xmm);
+		self.push_u8(imm);
+	}
+
+	pub fn pxor(&mut self, destination: u8, source: u8) {
+		self.push_xmm_xmm(0xEF, destination, source);
+	}
+
 	pub fn push_eax(&mut self) {
 		self.push_u8(0x50);
 	}
@@ -319,13 +473,6 @@ impl CodeEmitter {
 		self.push_u32(imm);
 	}
 
-	pub fn rdrand_ax(&mut self) {
-		self.push_u8(0x66);
-		self.push_u8(0x0F);
-		self.push_u8(0xC7);
-		self.push_u8(0xF0);
-	}
-
 	pub fn ret(&mut self) {
 		self.push_u8(0xC3);
 	}
@@ -344,14 +491,20 @@ impl CodeEmitter {
 		self.push_u32(m as *const u8 as u32);
 	}
 
+	pub fn setne_al(&mut self) {
+		self.push_u8(0x0F);
+		self.push_u8(0x95);
+		self.push_u8(0xC0);
+	}
+
 	pub fn shr_al(&mut self) {
 		self.push_u8(0xD0);
 		self.push_u8(0xE8);
 	}
 
-	pub fn shr_al_imm(&mut self, imm: u8) {
+	pub fn shr_cl_imm(&mut self, imm: u8) {
 		self.push_u8(0xC0);
-		self.push_u8(0xE8);
+		self.push_u8(0xE9);
 		self.push_u8(imm);
 	}
 
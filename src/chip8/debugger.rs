@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::prelude::*;
+
+use chip8::Chip8;
+use chip8::MEMORY_SIZE;
+use chip8::instruction::Instruction;
+
+const HELP: &str = "s, step [count]        run one or count instructions
+n, next                run over CALL, up to the instruction after it
+c, continue            run until a breakpoint
+b, break [address]     add a breakpoint, or list them
+d, delete <address>    remove a breakpoint, all of them without address
+r, regs                show the registers
+k, stack               show the stack
+m, mem <address> [len] hexdump memory, 64 bytes by default
+l, list [address]      disassemble around PC or the address
+set <reg> <value>      set V0-VF, I, PC, DT, ST or SP
+w, write <address> <byte>...
+                       write bytes to memory
+q, quit                stop the emulator
+an empty line repeats the last command, numbers are hexadecimal";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+	// stops before the next instruction, after count more
+	Step(u32),
+	// stops when the call at this stack depth returns to the address
+	StepOver { register_sp: u8, address: u16 },
+	Continue
+}
+
+// console debugger, stops before instructions to take commands from stdin
+pub struct Debugger {
+	breakpoints: BTreeSet<u16>,
+	mode: Mode,
+	// changes with the stop addresses or the step mode, the recompiler rebuilds its blocks then
+	generation: u32,
+	last_command: String,
+	memory_edited: bool
+}
+
+impl Debugger {
+	// stops before the first instruction
+	pub fn new() -> Debugger {
+		Debugger {
+			breakpoints: BTreeSet::new(),
+			mode: Mode::Step(0),
+			generation: 0,
+			last_command: String::new(),
+			memory_edited: false
+		}
+	}
+
+	// whether the instruction at PC is where the debugger stops next
+	pub fn should_stop(&self, chip8: &Chip8) -> bool {
+		match self.mode {
+			Mode::Step(0) => true,
+			Mode::StepOver { register_sp, address } if chip8.register_pc == address && chip8.register_sp == register_sp => true,
+			_ => self.breakpoints.contains(&chip8.register_pc)
+		}
+	}
+
+	// the recompiler ends its blocks before these addresses, and runs single instructions when stepping
+	pub fn stop_addresses(&self) -> BTreeSet<u16> {
+		let mut addresses = self.breakpoints.clone();
+		if let Mode::StepOver { address, .. } = self.mode {
+			addresses.insert(address);
+		}
+		addresses
+	}
+
+	pub fn is_stepping(&self) -> bool {
+		matches!(self.mode, Mode::Step(_))
+	}
+
+	pub fn generation(&self) -> u32 {
+		self.generation
+	}
+
+	// called before every instruction, or every block of the recompiler, returns true when the memory was edited
+	pub fn before_instruction(&mut self, chip8: &mut Chip8) -> bool {
+		if !self.should_stop(chip8) {
+			if let Mode::Step(count) = self.mode {
+				self.mode = Mode::Step(count - 1);
+			}
+			return false;
+		}
+
+		if self.breakpoints.contains(&chip8.register_pc) && self.mode == Mode::Continue {
+			println!("breakpoint at {:03X}", chip8.register_pc);
+		}
+		let stops = (self.stop_addresses(), self.is_stepping());
+		self.mode = Mode::Continue;
+		self.memory_edited = false;
+		print_registers(chip8);
+		self.print_instruction(chip8, chip8.register_pc);
+
+		let stdin = io::stdin();
+		self.mode = loop {
+			print!("(debug) ");
+			let _ = io::stdout().flush();
+			let mut line = String::new();
+			match stdin.lock().read_line(&mut line) {
+				// no console left, the emulator keeps running
+				Ok(0) | Err(_) => {
+					self.breakpoints.clear();
+					break Mode::Continue;
+				},
+				Ok(_) => ()
+			}
+
+			let line = match line.trim() {
+				"" => self.last_command.clone(),
+				line => line.to_string()
+			};
+			self.last_command = line.clone();
+			let words: Vec<&str> = line.split_whitespace().collect();
+			match self.execute(chip8, &words) {
+				Ok(Some(mode)) => break mode,
+				Ok(None) => (),
+				Err(error) => println!("{}", error)
+			}
+		};
+
+		if (self.stop_addresses(), self.is_stepping()) != stops {
+			self.generation += 1;
+		}
+		self.memory_edited
+	}
+
+	// > marks PC and * breakpoints
+	fn print_instruction(&self, chip8: &Chip8, address: u16) {
+		let instruction = Instruction::decode(&chip8.memory, address, &chip8.quirks);
+		let marker = if address == chip8.register_pc { '>' } else if self.breakpoints.contains(&address) { '*' } else { ' ' };
+		let (high_byte, low_byte) = (chip8.memory[address as usize], chip8.memory[address as usize + 1]);
+		println!("{}{:03X}: {:02X}{:02X}  {}", marker, address, high_byte, low_byte, instruction);
+	}
+
+	// returns how to resume, none to keep reading commands
+	fn execute(&mut self, chip8: &mut Chip8, words: &[&str]) -> Result<Option<Mode>, String> {
+		let argument = |index: usize| words.get(index).ok_or_else(|| format!("{} expects more arguments, see help", words[0])).and_then(|word| parse_number(word));
+
+		match words.first().cloned().unwrap_or("") {
+			"s" | "step" => {
+				let count = if words.len() > 1 { argument(1)? } else { 1 };
+				Ok(Some(Mode::Step(count.saturating_sub(1))))
+			},
+			"n" | "next" => {
+				let instruction = Instruction::decode(&chip8.memory, chip8.register_pc, &chip8.quirks);
+				match instruction.opcode {
+					(0x2, ..) => Ok(Some(Mode::StepOver { register_sp: chip8.register_sp, address: chip8.register_pc + 2 })),
+					_ => Ok(Some(Mode::Step(0)))
+				}
+			},
+			"c" | "continue" => Ok(Some(Mode::Continue)),
+			"b" | "break" if words.len() == 1 => {
+				let addresses: Vec<String> = self.breakpoints.iter().map(|address| format!("{:03X}", address)).collect();
+				println!("breakpoints: {}", if addresses.is_empty() { "none".to_string() } else { addresses.join(" ") });
+				Ok(None)
+			},
+			"b" | "break" => {
+				self.breakpoints.insert(address(argument(1)?)?);
+				Ok(None)
+			},
+			"d" | "delete" if words.len() == 1 => {
+				self.breakpoints.clear();
+				Ok(None)
+			},
+			"d" | "delete" => {
+				self.breakpoints.remove(&address(argument(1)?)?);
+				Ok(None)
+			},
+			"r" | "regs" => {
+				print_registers(chip8);
+				Ok(None)
+			},
+			"k" | "stack" => {
+				let depth = chip8.register_sp.wrapping_add(1) as usize;
+				if depth == 0 {
+					println!("stack empty");
+				}
+				for (level, address) in chip8.stack[..depth].iter().enumerate().rev() {
+					println!("{:2}: {:03X}", level, address);
+				}
+				Ok(None)
+			},
+			"m" | "mem" => {
+				let start = address(argument(1)?)? as usize;
+				let length = if words.len() > 2 { argument(2)? as usize } else { 64 };
+				let end = ::std::cmp::min(start + length, MEMORY_SIZE);
+				for row in (start..end).step_by(16) {
+					let bytes = &chip8.memory[row..::std::cmp::min(row + 16, end)];
+					let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+					let text: String = bytes.iter().map(|&byte| if (0x20..0x7F).contains(&byte) { byte as char } else { '.' }).collect();
+					println!("{:03X}: {:<47}  {}", row, hex.join(" "), text);
+				}
+				Ok(None)
+			},
+			"l" | "list" => {
+				let center = if words.len() > 1 { address(argument(1)?)? } else { chip8.register_pc };
+				let start = center.saturating_sub(8);
+				for address in (start..center + 10).step_by(2).filter(|&address| (address as usize) < MEMORY_SIZE - 1) {
+					self.print_instruction(chip8, address);
+				}
+				Ok(None)
+			},
+			"set" => {
+				let name = words.get(1).ok_or_else(|| "set expects a register and a value".to_string())?.to_uppercase();
+				let value = argument(2)?;
+				match name.as_str() {
+					"I" => chip8.register_i = value as u16 & 0xFFF,
+					"PC" if value as usize >= MEMORY_SIZE - 1 => return Err(format!("{:X} is past the end of memory", value)),
+					"PC" => chip8.register_pc = value as u16,
+					"DT" => chip8.register_dt = value as u8,
+					"ST" => chip8.register_st = value as u8,
+					"SP" if value != 0xFF && value as usize >= chip8.stack.len() => return Err(format!("SP is FF for an empty stack or below {:X}", chip8.stack.len())),
+					"SP" => chip8.register_sp = value as u8,
+					_ if name.starts_with('V') && name.len() == 2 => {
+						let x = usize::from_str_radix(&name[1..], 16).map_err(|_| format!("unknown register '{}'", name))?;
+						chip8.register_v[x] = value as u8;
+					},
+					_ => return Err(format!("unknown register '{}'", name))
+				}
+				Ok(None)
+			},
+			"w" | "write" => {
+				let start = address(argument(1)?)? as usize;
+				if words.len() < 3 {
+					return Err("write expects an address and bytes".to_string());
+				}
+				for index in 2..words.len() {
+					let location = start + index - 2;
+					if location >= MEMORY_SIZE {
+						return Err("writing past the end of memory".to_string());
+					}
+					chip8.memory[location] = argument(index)? as u8;
+					self.memory_edited = true;
+				}
+				Ok(None)
+			},
+			"q" | "quit" => {
+				chip8.quit();
+				Ok(Some(Mode::Continue))
+			},
+			"h" | "help" => {
+				println!("{}", HELP);
+				Ok(None)
+			},
+			"" => Ok(None),
+			command => Err(format!("unknown command '{}', see help", command))
+		}
+	}
+}
+
+// hexadecimal, with or without 0x
+fn parse_number(word: &str) -> Result<u32, String> {
+	let digits = if word.starts_with("0x") || word.starts_with("0X") { &word[2..] } else { word };
+	u32::from_str_radix(digits, 16).map_err(|_| format!("'{}' isn't a hexadecimal number", word))
+}
+
+fn address(value: u32) -> Result<u16, String> {
+	if (value as usize) < MEMORY_SIZE {
+		Ok(value as u16)
+	} else {
+		Err(format!("{:X} is past the end of memory", value))
+	}
+}
+
+fn print_registers(chip8: &Chip8) {
+	println!("PC={:03X} I={:03X} DT={:02X} ST={:02X} SP={:02X}", chip8.register_pc, chip8.register_i, chip8.register_dt, chip8.register_st, chip8.register_sp);
+	let registers: Vec<String> = chip8.register_v.iter().enumerate().map(|(x, value)| format!("V{:X}={:02X}", x, value)).collect();
+	println!("{}", registers.join(" "));
+}
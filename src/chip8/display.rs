@@ -1,78 +1,122 @@
-extern crate sdl2;
-
-use self::sdl2::video::Window;
-use self::sdl2::render::Canvas;
-use self::sdl2::rect::Rect;
-use self::sdl2::pixels::Color;
-
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
-const DISPLAY_SCALE: u32 = 8;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
 
 pub struct Display {
-	frame_buffer: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
-	canvas: Canvas<Window>
+	// one bit per pixel, the leftmost pixel in the most significant bit
+	rows: [u64; DISPLAY_HEIGHT],
+	// unpacked by frame_buffer from unpacked_rows, only the rows that changed since are unpacked again
+	pixels: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+	unpacked_rows: [u64; DISPLAY_HEIGHT]
 }
 
 impl Display {
-	pub fn new(sdl_context: &sdl2::Sdl) -> Display {
-		let video_subsystem = sdl_context.video().unwrap();
-		let window = video_subsystem.window("chip8dynarec", DISPLAY_WIDTH as u32 * DISPLAY_SCALE, DISPLAY_HEIGHT as u32 * DISPLAY_SCALE)
-    								.position_centered()
-    								.opengl()
-    								.build()
-    								.unwrap();
+	pub fn new() -> Display {
+		Display::from_rows([0; DISPLAY_HEIGHT])
+	}
 
+	pub fn from_rows(rows: [u64; DISPLAY_HEIGHT]) -> Display {
 		Display {
-			frame_buffer: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
-			canvas: window.into_canvas().build().unwrap()
+			rows,
+			pixels: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+			unpacked_rows: [0; DISPLAY_HEIGHT]
 		}
 	}
 
-	pub extern "stdcall" fn clear(&mut self) {
-		for y in 0..DISPLAY_HEIGHT {
-			for x in 0..DISPLAY_WIDTH {
-				self.frame_buffer[y * DISPLAY_WIDTH + x] = 0;
+	pub fn width(&self) -> usize {
+		DISPLAY_WIDTH
+	}
+
+	pub fn height(&self) -> usize {
+		DISPLAY_HEIGHT
+	}
+
+	pub fn rows(&self) -> &[u64] {
+		&self.rows
+	}
+
+	pub fn pixel(&self, x: usize, y: usize) -> u8 {
+		(self.rows[y] >> (DISPLAY_WIDTH - 1 - x)) as u8 & 1
+	}
+
+	// one byte per pixel, 1 when lit
+	pub fn frame_buffer(&mut self) -> &[u8] {
+		let rows = self.rows.iter().zip(self.unpacked_rows.iter_mut());
+		for ((&row, unpacked_row), pixels) in rows.zip(self.pixels.chunks_mut(DISPLAY_WIDTH)) {
+			if row != *unpacked_row {
+				unpack_row(row, pixels);
+				*unpacked_row = row;
 			}
 		}
+		&self.pixels
 	}
 
-	pub extern "stdcall" fn draw_sprite(&mut self, x_position: u8, mut y_position: u8, sprite: &[u8]) -> bool {
-		let mut pixel_erased = false;
-		y_position %= DISPLAY_HEIGHT as u8;
+	// the same bytes as frame_buffer, unpacked from the rows into a buffer of the caller
+	pub fn copy_frame_buffer(&self, frame_buffer: &mut [u8]) {
+		for (&row, pixels) in self.rows.iter().zip(frame_buffer.chunks_mut(DISPLAY_WIDTH)) {
+			unpack_row(row, pixels);
+		}
+	}
 
-		for byte in sprite {
-			for i in 0..8 {
-				let offset = y_position as usize * DISPLAY_WIDTH + (x_position as usize + i) % DISPLAY_WIDTH;
-				let old_pixel = self.frame_buffer[offset];
+	pub extern "stdcall" fn clear(&mut self) {
+		self.rows = [0; DISPLAY_HEIGHT];
+	}
 
-				self.frame_buffer[offset] ^= (byte >> (7 - i)) & 1;
-				
-				if old_pixel == 1 && self.frame_buffer[offset] == 0 {
-					pixel_erased = true;
-				}
-			}
+	pub fn draw_sprite(&mut self, x_position: u8, y_position: u8, sprite: &[u8]) -> bool {
+		let mut erased = 0;
 
-			y_position = (y_position + 1) % DISPLAY_HEIGHT as u8;
+		for (i, &byte) in sprite.iter().enumerate() {
+			let y = (y_position as usize + i) % DISPLAY_HEIGHT;
+			// rows are as wide as a u64, rotating wraps the sprite around the screen
+			let sprite_row = ((byte as u64) << (DISPLAY_WIDTH - 8)).rotate_right(x_position as u32);
+			erased |= self.rows[y] & sprite_row;
+			self.rows[y] ^= sprite_row;
 		}
 
-		pixel_erased
+		erased != 0
 	}
+}
 
-	pub fn refresh(&mut self) {
-		self.canvas.set_draw_color(Color::RGB(0x00, 0x00, 0x00));
-		self.canvas.clear();
-		self.canvas.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
-
-		for y in 0..DISPLAY_HEIGHT {
-			for x in 0..DISPLAY_WIDTH {
-				if self.frame_buffer[y * DISPLAY_WIDTH + x] == 1 {
-					let pixel = Rect::new((x * DISPLAY_SCALE as usize) as i32, (y * DISPLAY_SCALE as usize) as i32, DISPLAY_SCALE, DISPLAY_SCALE);
-					let _ = self.canvas.fill_rect(pixel);
-				}
-			}
+impl Default for Display {
+	fn default() -> Display {
+		Display::new()
+	}
+}
+
+fn unpack_row(row: u64, pixels: &mut [u8]) {
+	for (x, pixel) in pixels.iter_mut().enumerate() {
+		*pixel = (row >> (DISPLAY_WIDTH - 1 - x)) as u8 & 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frame_buffer_follows_the_rows() {
+		let mut display = Display::new();
+		// wraps around both edges
+		assert!(!display.draw_sprite(60, 30, &[0xF0, 0x81, 0xFF]));
+		assert!(display.draw_sprite(60, 31, &[0x80]));
+
+		let mut unpacked = [0xAA; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+		display.copy_frame_buffer(&mut unpacked);
+		assert_eq!(&unpacked[..], display.frame_buffer());
+		for (index, &pixel) in unpacked.iter().enumerate() {
+			assert_eq!(pixel, display.pixel(index % DISPLAY_WIDTH, index / DISPLAY_WIDTH));
 		}
+		assert_eq!(display.pixel(60, 30), 1);
+		assert_eq!(display.pixel(3, 31), 1);
+		assert_eq!(display.pixel(60, 31), 0);
+		assert_eq!(display.pixel(0, 0), 1);
+
+		// only the rows drawn since the last call are unpacked again
+		display.draw_sprite(0, 0, &[0x80]);
+		assert_eq!(display.frame_buffer()[0], 0);
+		assert_eq!(&display.frame_buffer()[DISPLAY_WIDTH..], &unpacked[DISPLAY_WIDTH..]);
 
-		self.canvas.present();
+		assert_eq!(Display::from_rows(display.rows).frame_buffer(), display.frame_buffer());
+		display.clear();
+		assert!(display.frame_buffer().iter().all(|&pixel| pixel == 0));
 	}
 }
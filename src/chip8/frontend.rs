@@ -0,0 +1,41 @@
+use chip8::display::Display;
+use chip8::keyboard::Keyboard;
+
+// emulator controls, handled by Chip8::run between frames
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+	TogglePause,
+	// runs a single frame and pauses
+	StepFrame,
+	Reset,
+	// no frame pacing while enabled
+	FastForward(bool),
+	ToggleSlowMotion,
+	// numbered slots in Chip8::state_directory
+	SaveState(u8),
+	LoadState(u8),
+	// steps back through Chip8::rewind while enabled
+	Rewind(bool)
+}
+
+// what the emulated machine needs from the host, called at ~60Hz
+pub trait Frontend {
+	fn update_key_states(&mut self, keyboard: &mut Keyboard);
+	fn present(&mut self, display: &Display);
+	// the user asked to quit, checked after the key states are updated
+	fn is_closed(&self) -> bool {
+		false
+	}
+	// actions requested since the last call, oldest first
+	fn next_action(&mut self) -> Option<Action> {
+		None
+	}
+}
+
+// no window and no input: the machine is driven through the Chip8 API
+pub struct Headless;
+
+impl Frontend for Headless {
+	fn update_key_states(&mut self, _: &mut Keyboard) {}
+	fn present(&mut self, _: &Display) {}
+}
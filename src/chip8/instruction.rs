@@ -0,0 +1,144 @@
+use std::fmt;
+
+use chip8::MEMORY_SIZE;
+use chip8::quirks::Quirks;
+
+#[derive(Clone, Copy)]
+pub struct Instruction {
+	pub address: u16,
+	pub opcode: (u8, u8, u8, u8),
+	pub nnn: u16,
+	pub low_byte: u8,
+	pub x: usize,
+	pub y: usize
+}
+
+impl Instruction {
+	pub fn decode(memory: &[u8; MEMORY_SIZE], address: u16, quirks: &Quirks) -> Instruction {
+		let high_byte = memory[address as usize];
+		let low_byte = memory[address as usize + 1];
+
+		let mut instruction = Instruction {
+			address,
+			opcode: (high_byte >> 4, high_byte & 0x0F, low_byte >> 4, low_byte & 0x0F),
+			nnn: ((high_byte as u16 & 0x0F) << 8) | low_byte as u16,
+			low_byte,
+			x: high_byte as usize & 0x0F,
+			y: low_byte as usize >> 4
+		};
+
+		// shifting VX in place is shifting VY with Y = X
+		match instruction.opcode {
+			(0x8, _, _, 0x6) | (0x8, _, _, 0xE) if quirks.shift_vx => instruction.y = instruction.x,
+			_ => ()
+		}
+		instruction
+	}
+
+	// conditionally skip the next instruction
+	pub fn is_skip(&self) -> bool {
+		matches!(self.opcode,
+			(0x3, ..) |
+			(0x4, ..) |
+			(0x5, _, _, 0x0) |
+			(0x9, _, _, 0x0) |
+			(0xE, _, 0x9, 0xE) |
+			(0xE, _, 0xA, 0x1))
+	}
+
+	// instructions after which the PC isn't simply incremented
+	pub fn is_jump(&self) -> bool {
+		matches!(self.opcode,
+			(0x0, 0x0, 0xE, 0xE) |
+			(0x1, ..) |
+			(0x2, ..) |
+			(0xB, ..))
+	}
+
+	// FX0A leaves the block while it waits for a key
+	pub fn is_key_wait(&self) -> bool {
+		matches!(self.opcode, (0xF, _, 0x0, 0xA))
+	}
+
+	// bit mask of the V registers read by the instruction
+	pub fn reads(&self) -> u16 {
+		let x = 1 << self.x;
+		let y = 1 << self.y;
+		match self.opcode {
+			(0x3, ..) | (0x4, ..) | (0x7, ..) => x,
+			(0x5, _, _, 0x0) | (0x9, _, _, 0x0) => x | y,
+			(0x8, _, _, 0x0) | (0x8, _, _, 0x6) | (0x8, _, _, 0xE) => y,
+			(0x8, ..) => x | y,
+			(0xB, ..) => 1 | x,
+			(0xD, ..) => x | y,
+			(0xE, ..) => x,
+			(0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) | (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x3) => x,
+			(0xF, _, 0x5, 0x5) => 0xFFFF >> (15 - self.x),
+			_ => 0
+		}
+	}
+
+	// bit mask of the V registers overwritten by the instruction
+	pub fn writes(&self) -> u16 {
+		let x = 1 << self.x;
+		match self.opcode {
+			(0x6, ..) | (0x7, ..) | (0xC, ..) => x,
+			(0x8, _, _, 0x4) | (0x8, _, _, 0x5) | (0x8, _, _, 0x6) | (0x8, _, _, 0x7) | (0x8, _, _, 0xE) => x | 0x8000,
+			(0x8, ..) => x,
+			(0xD, ..) => 0x8000,
+			(0xF, _, 0x0, 0x7) | (0xF, _, 0x0, 0xA) => x,
+			(0xF, _, 0x6, 0x5) => 0xFFFF >> (15 - self.x),
+			_ => 0
+		}
+	}
+
+	// arithmetic instructions that also update VF
+	pub fn writes_flag(&self) -> bool {
+		matches!(self.opcode, (0x8, _, _, 0x4) | (0x8, _, _, 0x5) | (0x8, _, _, 0x6) | (0x8, _, _, 0x7) | (0x8, _, _, 0xE))
+	}
+}
+
+// Cowgod's mnemonics, for the debugger
+impl fmt::Display for Instruction {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		let (x, y, nnn, nn) = (self.x, self.y, self.nnn, self.low_byte);
+		match self.opcode {
+			(0x0, 0x0, 0xE, 0x0) => write!(formatter, "CLS"),
+			(0x0, 0x0, 0xE, 0xE) => write!(formatter, "RET"),
+			(0x0, ..) => write!(formatter, "SYS {:03X}", nnn),
+			(0x1, ..) => write!(formatter, "JP {:03X}", nnn),
+			(0x2, ..) => write!(formatter, "CALL {:03X}", nnn),
+			(0x3, ..) => write!(formatter, "SE V{:X}, {:02X}", x, nn),
+			(0x4, ..) => write!(formatter, "SNE V{:X}, {:02X}", x, nn),
+			(0x5, _, _, 0x0) => write!(formatter, "SE V{:X}, V{:X}", x, y),
+			(0x6, ..) => write!(formatter, "LD V{:X}, {:02X}", x, nn),
+			(0x7, ..) => write!(formatter, "ADD V{:X}, {:02X}", x, nn),
+			(0x8, _, _, 0x0) => write!(formatter, "LD V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x1) => write!(formatter, "OR V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x2) => write!(formatter, "AND V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x3) => write!(formatter, "XOR V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x4) => write!(formatter, "ADD V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x5) => write!(formatter, "SUB V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x6) => write!(formatter, "SHR V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0x7) => write!(formatter, "SUBN V{:X}, V{:X}", x, y),
+			(0x8, _, _, 0xE) => write!(formatter, "SHL V{:X}, V{:X}", x, y),
+			(0x9, _, _, 0x0) => write!(formatter, "SNE V{:X}, V{:X}", x, y),
+			(0xA, ..) => write!(formatter, "LD I, {:03X}", nnn),
+			(0xB, ..) => write!(formatter, "JP V0, {:03X}", nnn),
+			(0xC, ..) => write!(formatter, "RND V{:X}, {:02X}", x, nn),
+			(0xD, _, _, n) => write!(formatter, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+			(0xE, _, 0x9, 0xE) => write!(formatter, "SKP V{:X}", x),
+			(0xE, _, 0xA, 0x1) => write!(formatter, "SKNP V{:X}", x),
+			(0xF, _, 0x0, 0x7) => write!(formatter, "LD V{:X}, DT", x),
+			(0xF, _, 0x0, 0xA) => write!(formatter, "LD V{:X}, K", x),
+			(0xF, _, 0x1, 0x5) => write!(formatter, "LD DT, V{:X}", x),
+			(0xF, _, 0x1, 0x8) => write!(formatter, "LD ST, V{:X}", x),
+			(0xF, _, 0x1, 0xE) => write!(formatter, "ADD I, V{:X}", x),
+			(0xF, _, 0x2, 0x9) => write!(formatter, "LD F, V{:X}", x),
+			(0xF, _, 0x3, 0x3) => write!(formatter, "LD B, V{:X}", x),
+			(0xF, _, 0x5, 0x5) => write!(formatter, "LD [I], V{:X}", x),
+			(0xF, _, 0x6, 0x5) => write!(formatter, "LD V{:X}, [I]", x),
+			_ => write!(formatter, "DW {:02X}{:02X}", (self.nnn >> 8) as u8 | (self.opcode.0 << 4), self.low_byte)
+		}
+	}
+}
@@ -1,5 +1,3 @@
-extern crate rand;
-
 use chip8::Chip8;
 
 pub struct Interpreter;
@@ -21,7 +19,12 @@ impl Interpreter {
 				chip8.register_pc = chip8.stack[chip8.register_sp as usize];
 				chip8.register_sp = chip8.register_sp.wrapping_sub(1);
 			},
-			(0x1, ..) => chip8.register_pc = nnn,
+			(0x1, ..) => {
+				chip8.register_pc = nnn;
+				if chip8.is_idle_loop(nnn) {
+					chip8.wait_next_frame(0);
+				}
+			},
 			(0x2, ..) => {
 				chip8.register_sp = chip8.register_sp.wrapping_add(1);
 				chip8.stack[chip8.register_sp as usize] = chip8.register_pc;
@@ -58,6 +61,7 @@ impl Interpreter {
 				chip8.register_v[x] = chip8.register_v[x].wrapping_sub(chip8.register_v[y]);
 			},
 			(0x8, _, _, 0x6) => {
+				let y = if chip8.quirks.shift_vx { x } else { y };
 				chip8.register_v[0xF] = chip8.register_v[y] & 1;
 				chip8.register_v[x] = chip8.register_v[y] >> 1;
 			},
@@ -66,6 +70,7 @@ impl Interpreter {
 				chip8.register_v[x] = chip8.register_v[y].wrapping_sub(chip8.register_v[x]);
 			},
 			(0x8, _, _, 0xE) => {
+				let y = if chip8.quirks.shift_vx { x } else { y };
 				chip8.register_v[0xF] = chip8.register_v[y] >> 7;
 				chip8.register_v[x] = chip8.register_v[y] << 1;
 			},
@@ -75,8 +80,11 @@ impl Interpreter {
 				}
 			},
 			(0xA, ..) => chip8.register_i = nnn,
-			(0xB, ..) => chip8.register_pc = nnn + chip8.register_v[0] as u16,
-			(0xC, ..) => chip8.register_v[x] = rand::random::<u8>() & low_byte,
+			(0xB, ..) => {
+				let offset = if chip8.quirks.jump_vx { chip8.register_v[x] } else { chip8.register_v[0] };
+				chip8.register_pc = nnn + offset as u16;
+			},
+			(0xC, ..) => chip8.register_v[x] = chip8.random_byte() & low_byte,
 			(0xD, _, _, n) => {
 				let sprite = &chip8.memory[chip8.register_i as usize .. chip8.register_i as usize + n as usize];
 				chip8.register_v[0xF] = chip8.display.draw_sprite(chip8.register_v[x], chip8.register_v[y], sprite) as u8;
@@ -92,7 +100,12 @@ impl Interpreter {
 				}
 			},
 			(0xF, _, 0x0, 0x7) => chip8.register_v[x] = chip8.register_dt,
-			(0xF, _, 0x0, 0xA) => chip8.register_v[x] = chip8.keyboard.wait_key_press(),
+			(0xF, _, 0x0, 0xA) => {
+				if !chip8.wait_key_release(x as u32) {
+					chip8.register_pc -= 2;
+					chip8.skip_to_next_frame(0);
+				}
+			},
 			(0xF, _, 0x1, 0x5) => chip8.register_dt = chip8.register_v[x],
 			(0xF, _, 0x1, 0x8) => chip8.register_st = chip8.register_v[x],
 			(0xF, _, 0x1, 0xE) => chip8.register_i += chip8.register_v[x] as u16,
@@ -104,14 +117,18 @@ impl Interpreter {
 			},
 			(0xF, _, 0x5, 0x5) => {
 				for i in 0..(x + 1) {
-					chip8.memory[chip8.register_i as usize] = chip8.register_v[i];
-					chip8.register_i += 1;
+					chip8.memory[chip8.register_i as usize + i] = chip8.register_v[i];
+				}
+				if !chip8.quirks.load_store_keep_i {
+					chip8.register_i += x as u16 + 1;
 				}
 			},
 			(0xF, _, 0x6, 0x5) => {
 				for i in 0..(x + 1) {
-					chip8.register_v[i] = chip8.memory[chip8.register_i as usize];
-					chip8.register_i += 1;
+					chip8.register_v[i] = chip8.memory[chip8.register_i as usize + i];
+				}
+				if !chip8.quirks.load_store_keep_i {
+					chip8.register_i += x as u16 + 1;
 				}
 			},
 			_ => panic!("unknown opcode")
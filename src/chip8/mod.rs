@@ -1,20 +1,30 @@
 mod chip8;
 mod keyboard;
 mod display;
+mod frontend;
+mod quirks;
+mod savestate;
+mod rewind;
+mod movie;
+mod random;
+#[cfg(feature="debugger")]
+mod debugger;
 
-#[cfg(feature="interpreter")]
 mod interpreter;
-
-#[cfg(not(feature="interpreter"))]
+mod threadedinterpreter;
 mod recompiler;
-
-#[cfg(not(feature="interpreter"))]
 mod codeemitter;
-
-#[cfg(not(feature="interpreter"))]
 mod codecache;
+mod instruction;
+mod optimizer;
 
-pub use self::chip8::Chip8;
+pub use self::chip8::{rom_hash, Chip8, Engine};
+pub use self::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use self::frontend::{Action, Frontend, Headless};
+pub use self::keyboard::Keyboard;
+pub use self::movie::Movie;
+pub use self::quirks::Quirks;
+pub use self::rewind::Rewind;
 
 const MEMORY_SIZE: usize = 0x1000;
 const ROM_START_ADDRESS: u16 = 0x200;
@@ -0,0 +1,232 @@
+use std::fmt;
+
+use chip8::chip8::Engine;
+use chip8::keyboard::Keyboard;
+use chip8::quirks::Quirks;
+
+// the key states of every frame of a session from power-on, saved as text:
+//
+//   rom = 97349b2b6ca8e927
+//   engine = recompiler
+//   quirks = none
+//   ipf = 10
+//   seed = 0
+//   length = 600
+//   # frame = keys pressed from that frame on, - for none, the first frame is 0
+//   12 = 5
+//   20 = 5 A
+//   31 = -
+//   # state hash at the start of the frame, to detect desyncs
+//   hash 31 = 3fa4c2d0e1b28867
+//
+// every line but the inputs is optional, so that input scripts can be written by hand
+pub struct Movie {
+	// checked before playing when set
+	pub rom_hash: Option<u64>,
+	// the engines don't count the cycles of a frame alike, hashes only match with the same one
+	pub engine: Option<Engine>,
+	pub quirks: Quirks,
+	pub cycles_per_frame: u32,
+	// of the CXNN random numbers
+	pub seed: u64,
+	// frames in the movie, the frontend's keys are used after them
+	pub length: Option<u64>,
+	// key states as a bit per key, by frame
+	pub inputs: Vec<(u64, u16)>,
+	pub hashes: Vec<(u64, u64)>,
+	playing: bool,
+	record_hashes: bool,
+	// pressed during the current frame
+	keys: u16,
+	next_input: usize,
+	next_hash: usize,
+	desync: Option<u64>
+}
+
+impl Movie {
+	pub fn new(rom_hash: u64, engine: Engine, quirks: Quirks, cycles_per_frame: u32, seed: u64) -> Movie {
+		Movie {
+			rom_hash: Some(rom_hash),
+			engine: Some(engine),
+			quirks,
+			cycles_per_frame,
+			seed,
+			length: None,
+			inputs: Vec::new(),
+			hashes: Vec::new(),
+			playing: false,
+			record_hashes: false,
+			keys: 0,
+			next_input: 0,
+			next_hash: 0,
+			desync: None
+		}
+	}
+
+	pub fn parse(text: &str, default_cycles_per_frame: u32) -> Result<Movie, String> {
+		let mut movie = Movie::new(0, Engine::default(), Quirks::default(), default_cycles_per_frame, 0);
+		movie.rom_hash = None;
+		movie.engine = None;
+
+		for (index, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let error = |message: String| format!("line {}: {}", index + 1, message);
+			let mut parts = line.splitn(2, '=').map(str::trim);
+			let (key, value) = match (parts.next(), parts.next()) {
+				(Some(key), Some(value)) => (key, value),
+				_ => return Err(error(format!("expected 'name = value', got '{}'", line)))
+			};
+			let number = |value: &str| value.parse::<u64>().map_err(|_| error(format!("'{}' isn't a number", value)));
+			let hex = |value: &str| u64::from_str_radix(value, 16).map_err(|_| error(format!("'{}' isn't a hexadecimal hash", value)));
+
+			match key {
+				"rom" => movie.rom_hash = Some(hex(value)?),
+				"engine" => movie.engine = Some(value.parse().map_err(&error)?),
+				"quirks" => movie.quirks = Quirks::parse(value).map_err(&error)?,
+				"ipf" => movie.cycles_per_frame = number(value)? as u32,
+				"seed" => movie.seed = number(value)?,
+				"length" => movie.length = Some(number(value)?),
+				_ if key.starts_with("hash ") => movie.hashes.push((number(key[5..].trim())?, hex(value)?)),
+				_ => movie.inputs.push((number(key)?, parse_keys(value).map_err(&error)?))
+			}
+		}
+
+		if movie.cycles_per_frame == 0 {
+			return Err("ipf must be at least 1".to_string());
+		}
+		// hand-written lines may be out of order
+		movie.inputs.sort_by_key(|input| input.0);
+		movie.hashes.sort_by_key(|hash| hash.0);
+		if movie.length.is_none() {
+			// scripts end with their last line
+			let last_input = movie.inputs.last().map(|input| input.0);
+			let last_hash = movie.hashes.last().map(|hash| hash.0);
+			movie.length = last_input.into_iter().chain(last_hash).max().map(|frame| frame + 1);
+		}
+		Ok(movie)
+	}
+
+	pub fn start_recording(&mut self, record_hashes: bool) {
+		self.playing = false;
+		self.record_hashes = record_hashes;
+		self.inputs.clear();
+		self.hashes.clear();
+		self.keys = 0;
+		self.length = Some(0);
+	}
+
+	pub fn start_playing(&mut self) {
+		self.playing = true;
+		self.keys = 0;
+		self.next_input = 0;
+		self.next_hash = 0;
+		self.desync = None;
+	}
+
+	pub fn is_playing(&self) -> bool {
+		self.playing
+	}
+
+	// the movie was played to the end, the frontend has the keys back
+	pub fn is_finished(&self, frame: u64) -> bool {
+		self.playing && self.length.is_some_and(|length| frame >= length)
+	}
+
+	// first frame starting with another state hash than recorded
+	pub fn desync(&self) -> Option<u64> {
+		self.desync
+	}
+
+	pub fn needs_hash(&self, frame: u64) -> bool {
+		if self.playing {
+			self.hashes[self.next_hash..].iter().take_while(|hash| hash.0 <= frame).any(|hash| hash.0 == frame)
+		} else {
+			self.record_hashes
+		}
+	}
+
+	// called at the start of each frame: records the keys pressed for it or presses
+	// the keys of the movie, hash is the machine's state hash when needs_hash asked for it
+	pub fn frame(&mut self, frame: u64, keyboard: &mut Keyboard, hash: Option<u64>) {
+		if !self.playing {
+			let keys = keyboard.key_states();
+			if keys != self.keys {
+				self.inputs.push((frame, keys));
+				self.keys = keys;
+			}
+			if let Some(hash) = hash {
+				self.hashes.push((frame, hash));
+			}
+			self.length = Some(frame);
+			return;
+		}
+
+		if self.is_finished(frame) {
+			return;
+		}
+		while self.next_input < self.inputs.len() && self.inputs[self.next_input].0 <= frame {
+			self.keys = self.inputs[self.next_input].1;
+			self.next_input += 1;
+		}
+		// the frontend updated the keyboard first
+		keyboard.set_key_states(self.keys);
+
+		while self.next_hash < self.hashes.len() && self.hashes[self.next_hash].0 <= frame {
+			let expected = self.hashes[self.next_hash];
+			self.next_hash += 1;
+			if expected.0 == frame && hash.is_some_and(|hash| hash != expected.1) && self.desync.is_none() {
+				eprintln!("movie desynced at frame {}", frame);
+				self.desync = Some(frame);
+			}
+		}
+	}
+}
+
+// hexadecimal digits separated by spaces, - for none
+fn parse_keys(keys: &str) -> Result<u16, String> {
+	if keys == "-" {
+		return Ok(0);
+	}
+	keys.split_whitespace().map(|key| match u8::from_str_radix(key, 16) {
+		Ok(key) if key < 0x10 => Ok(1 << key),
+		_ => Err(format!("'{}' isn't a key, expected 0 to F", key))
+	}).try_fold(0, |keys, key| Ok(keys | key?))
+}
+
+impl fmt::Display for Movie {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(rom_hash) = self.rom_hash {
+			writeln!(formatter, "rom = {:016x}", rom_hash)?;
+		}
+		if let Some(engine) = self.engine {
+			writeln!(formatter, "engine = {}", engine)?;
+		}
+		writeln!(formatter, "quirks = {}", self.quirks)?;
+		writeln!(formatter, "ipf = {}", self.cycles_per_frame)?;
+		writeln!(formatter, "seed = {}", self.seed)?;
+		if let Some(length) = self.length {
+			writeln!(formatter, "length = {}", length)?;
+		}
+
+		// hashes are written after the inputs of their frame
+		let mut hashes = self.hashes.iter().peekable();
+		for &(frame, keys) in self.inputs.iter() {
+			while let Some(&&(hash_frame, hash)) = hashes.peek() {
+				if hash_frame >= frame {
+					break;
+				}
+				writeln!(formatter, "hash {} = {:016x}", hash_frame, hash)?;
+				hashes.next();
+			}
+			let keys: Vec<String> = (0..16).filter(|key| keys & (1 << key) != 0).map(|key| format!("{:X}", key)).collect();
+			writeln!(formatter, "{} = {}", frame, if keys.is_empty() { "-".to_string() } else { keys.join(" ") })?;
+		}
+		for &(frame, hash) in hashes {
+			writeln!(formatter, "hash {} = {:016x}", frame, hash)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,185 @@
+use chip8::instruction::Instruction;
+
+const VF: u16 = 0x8000;
+
+#[derive(Clone, Copy, Default)]
+pub struct Hint {
+	// the instruction has no observable effect and can be left out
+	pub dead: bool,
+	// the VF update is overwritten before being read
+	pub dead_flag: bool,
+	// Vx (and VF for arithmetic instructions) are known constants after the instruction
+	pub result: Option<u8>,
+	pub flag: Option<u8>
+}
+
+pub fn optimize(block: &[Instruction]) -> Vec<Hint> {
+	let mut hints = vec![Hint::default(); block.len()];
+
+	if cfg!(feature="no-optimizer") {
+		return hints;
+	}
+
+	fold_constants(block, &mut hints);
+	eliminate_dead_stores(block, &mut hints);
+	hints
+}
+
+// instructions which may be skipped by the previous one
+fn is_conditional(block: &[Instruction], index: usize) -> bool {
+	index > 0 && block[index - 1].is_skip()
+}
+
+// instructions whose only effect is to write V registers
+fn is_pure(instruction: &Instruction) -> bool {
+	matches!(instruction.opcode, (0x6, ..) | (0x7, ..) | (0x8, ..) | (0xF, _, 0x0, 0x7))
+}
+
+fn fold_constants(block: &[Instruction], hints: &mut [Hint]) {
+	let mut constants: [Option<u8>; 16] = [None; 16];
+
+	for (index, (instruction, hint)) in block.iter().zip(hints.iter_mut()).enumerate() {
+		let x = instruction.x;
+		let vx = constants[x];
+		let vy = constants[instruction.y];
+
+		let folded = match (instruction.opcode, vx, vy) {
+			((0x6, ..), _, _) => Some((instruction.low_byte, None)),
+			((0x7, ..), Some(vx), _) => Some((vx.wrapping_add(instruction.low_byte), None)),
+			((0x8, _, _, 0x0), _, Some(vy)) => Some((vy, None)),
+			((0x8, _, _, 0x1), Some(vx), Some(vy)) => Some((vx | vy, None)),
+			((0x8, _, _, 0x2), Some(vx), Some(vy)) => Some((vx & vy, None)),
+			((0x8, _, _, 0x3), Some(vx), Some(vy)) => Some((vx ^ vy, None)),
+			// VF as destination of an arithmetic instruction is left to the regular code path
+			(_, _, _) if x == 0xF && instruction.writes_flag() => None,
+			((0x8, _, _, 0x4), Some(vx), Some(vy)) => Some((vx.wrapping_add(vy), Some((vx as u16 + vy as u16 > 0xFF) as u8))),
+			((0x8, _, _, 0x5), Some(vx), Some(vy)) => Some((vx.wrapping_sub(vy), Some((vx >= vy) as u8))),
+			((0x8, _, _, 0x6), _, Some(vy)) => Some((vy >> 1, Some(vy & 1))),
+			((0x8, _, _, 0x7), Some(vx), Some(vy)) => Some((vy.wrapping_sub(vx), Some((vx <= vy) as u8))),
+			((0x8, _, _, 0xE), _, Some(vy)) => Some((vy << 1, Some(vy >> 7))),
+			_ => None
+		};
+
+		for (i, constant) in constants.iter_mut().enumerate() {
+			if instruction.writes() & (1 << i) != 0 {
+				*constant = None;
+			}
+		}
+
+		if let Some((result, flag)) = folded {
+			// the registers hold either value after a skippable instruction
+			if !is_conditional(block, index) {
+				constants[x] = Some(result);
+				if flag.is_some() {
+					constants[0xF] = flag;
+				}
+			}
+			hint.result = Some(result);
+			hint.flag = flag;
+		}
+	}
+}
+
+fn eliminate_dead_stores(block: &[Instruction], hints: &mut [Hint]) {
+	// every register is live when leaving the block
+	let mut live: u16 = 0xFFFF;
+
+	for (index, (instruction, hint)) in block.iter().zip(hints.iter_mut()).enumerate().rev() {
+		let writes = instruction.writes();
+		// folded instructions don't read their operands anymore
+		let reads = if hint.result.is_some() { 0 } else { instruction.reads() };
+
+		if is_pure(instruction) && writes & live == 0 {
+			hint.dead = true;
+			continue;
+		}
+
+		if instruction.writes_flag() && instruction.x != 0xF && live & VF == 0 {
+			hint.dead_flag = true;
+		}
+
+		if instruction.is_jump() || instruction.is_key_wait() {
+			// the block may be left in the middle when the jump follows a skip
+			live = 0xFFFF;
+		} else if is_conditional(block, index) {
+			live |= reads;
+		} else {
+			live = (live & !writes) | reads;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chip8::MEMORY_SIZE;
+	use chip8::quirks::Quirks;
+
+	fn decode(opcodes: &[u16]) -> Vec<Instruction> {
+		let mut memory = [0; MEMORY_SIZE];
+		for (index, &opcode) in opcodes.iter().enumerate() {
+			memory[0x200 + index * 2] = (opcode >> 8) as u8;
+			memory[0x200 + index * 2 + 1] = opcode as u8;
+		}
+		(0..opcodes.len()).map(|index| Instruction::decode(&memory, 0x200 + index as u16 * 2, &Quirks::default())).collect()
+	}
+
+	#[cfg(not(feature="no-optimizer"))]
+	fn dead(opcodes: &[u16]) -> Vec<bool> {
+		optimize(&decode(opcodes)).iter().map(|hint| hint.dead).collect()
+	}
+
+	#[test]
+	#[cfg(not(feature="no-optimizer"))]
+	fn folds_constants() {
+		let hints = optimize(&decode(&[0x6105, 0x7103, 0x7203, 0x6AFF, 0x7A02, 0x6203, 0x8124]));
+		assert_eq!(hints[1].result, Some(8));
+		// V2 isn't known yet
+		assert_eq!(hints[2].result, None);
+		assert_eq!(hints[4].result, Some(0x01));
+		assert_eq!((hints[6].result, hints[6].flag), (Some(11), Some(0)));
+		// the folded 8124 reads nothing, only the last writes of V1, V2 and VA are left
+		let dead: Vec<bool> = hints.iter().map(|hint| hint.dead).collect();
+		assert_eq!(dead, [true, true, true, true, false, false, false]);
+	}
+
+	#[test]
+	#[cfg(not(feature="no-optimizer"))]
+	fn flags_read_as_vf() {
+		let dead_flags = |opcodes: &[u16]| optimize(&decode(opcodes)).iter().map(|hint| hint.dead_flag).collect::<Vec<bool>>();
+		// overwritten by the next carry
+		assert_eq!(dead_flags(&[0x8124, 0x8134]), [true, false]);
+		// 8F34 adds to VF, then writes its carry there
+		assert_eq!(dead_flags(&[0x8124, 0x8F34]), [false, false]);
+		// unless all it writes is overwritten
+		assert_eq!(dead_flags(&[0x8124, 0x8F34, 0x8134]), [true, false, false]);
+		assert_eq!(dead(&[0x8124, 0x8F34, 0x6F00]), [false, true, false]);
+	}
+
+	#[test]
+	#[cfg(not(feature="no-optimizer"))]
+	fn skipped_stores_keep_earlier_ones() {
+		assert_eq!(dead(&[0x6105, 0x6107]), [true, false]);
+		// 6107 may not run
+		assert_eq!(dead(&[0x6105, 0x3200, 0x6107]), [false, false, false]);
+		assert_eq!(dead(&[0x6105, 0xE29E, 0x6107]), [false, false, false]);
+	}
+
+	#[test]
+	#[cfg(not(feature="no-optimizer"))]
+	fn jumps_and_key_waits_leave_the_block() {
+		// superblocks go on after jumps, the block may be left there after a skip
+		assert_eq!(dead(&[0x6105, 0x1208, 0x6107]), [false, false, false]);
+		assert_eq!(dead(&[0x6105, 0x2208, 0x6107]), [false, false, false]);
+		assert_eq!(dead(&[0x6105, 0xF20A, 0x6107]), [false, false, false]);
+		let hints = optimize(&decode(&[0x8124, 0xF20A, 0x8134]));
+		assert!(!hints[0].dead_flag);
+	}
+
+	#[test]
+	#[cfg(feature="no-optimizer")]
+	fn no_optimizer_leaves_the_code_alone() {
+		let hints = optimize(&decode(&[0x6105, 0x7103, 0x6107, 0x8124, 0x8134]));
+		assert!(hints.iter().all(|hint| !hint.dead && !hint.dead_flag && hint.result.is_none() && hint.flag.is_none()));
+	}
+}
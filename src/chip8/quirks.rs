@@ -0,0 +1,80 @@
+use std::fmt;
+
+// behaviors that differ between CHIP-8 implementations
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quirks {
+	// 8XY6 and 8XYE shift VX in place instead of loading VY
+	pub shift_vx: bool,
+	// FX55 and FX65 leave I unchanged
+	pub load_store_keep_i: bool,
+	// BXNN jumps to XNN + VX instead of NNN + V0
+	pub jump_vx: bool
+}
+
+impl Quirks {
+	// original COSMAC VIP behavior
+	pub fn vip() -> Quirks {
+		Quirks {
+			shift_vx: false,
+			load_store_keep_i: false,
+			jump_vx: false
+		}
+	}
+
+	// SUPER-CHIP on the HP-48, expected by most later ROMs
+	pub fn schip() -> Quirks {
+		Quirks {
+			shift_vx: true,
+			load_store_keep_i: true,
+			jump_vx: true
+		}
+	}
+
+	pub fn preset(name: &str) -> Option<Quirks> {
+		match name {
+			"vip" => Some(Quirks::vip()),
+			"schip" => Some(Quirks::schip()),
+			_ => None
+		}
+	}
+
+	// a preset, none, or the enabled quirks separated by commas
+	pub fn parse(description: &str) -> Result<Quirks, String> {
+		if let Some(quirks) = Quirks::preset(description) {
+			return Ok(quirks);
+		}
+
+		let mut quirks = Quirks::vip();
+		if description == "none" {
+			return Ok(quirks);
+		}
+		for name in description.split(',').map(str::trim) {
+			match name {
+				"shift_vx" => quirks.shift_vx = true,
+				"load_store_keep_i" => quirks.load_store_keep_i = true,
+				"jump_vx" => quirks.jump_vx = true,
+				_ => return Err(format!("unknown quirk '{}', expected shift_vx, load_store_keep_i or jump_vx", name))
+			}
+		}
+		Ok(quirks)
+	}
+}
+
+// the enabled quirks as parsed back by Quirks::parse
+impl fmt::Display for Quirks {
+	fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		let names: Vec<&str> = [(self.shift_vx, "shift_vx"), (self.load_store_keep_i, "load_store_keep_i"), (self.jump_vx, "jump_vx")]
+			.iter().filter(|quirk| quirk.0).map(|quirk| quirk.1).collect();
+		if names.is_empty() {
+			write!(formatter, "none")
+		} else {
+			write!(formatter, "{}", names.join(", "))
+		}
+	}
+}
+
+impl Default for Quirks {
+	fn default() -> Quirks {
+		Quirks::vip()
+	}
+}
@@ -0,0 +1,33 @@
+// xorshift64*, small and the same on every host so that seeded runs can be replayed
+pub struct Random {
+	state: u64
+}
+
+impl Random {
+	pub fn new(seed: u64) -> Random {
+		// SplitMix64 spreads close seeds apart, the state must not be 0
+		let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+		z ^= z >> 31;
+		Random {
+			state: if z != 0 { z } else { 0x9E3779B97F4A7C15 }
+		}
+	}
+
+	pub fn from_state(state: u64) -> Option<Random> {
+		if state != 0 { Some(Random { state }) } else { None }
+	}
+
+	pub fn state(&self) -> u64 {
+		self.state
+	}
+
+	pub fn next_byte(&mut self) -> u8 {
+		self.state ^= self.state >> 12;
+		self.state ^= self.state << 25;
+		self.state ^= self.state >> 27;
+		// the high bits are the best mixed
+		(self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+	}
+}
@@ -1,329 +1,709 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use chip8::Chip8;
+use chip8::MEMORY_SIZE;
 use chip8::codeemitter::CodeEmitter;
 use chip8::codecache::CodeCache;
-use chip8::display::Display;
+use chip8::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use chip8::keyboard::Keyboard;
+use chip8::instruction::Instruction;
+use chip8::optimizer;
+use chip8::optimizer::Hint;
+use chip8::quirks::Quirks;
+
+// executions of a block before it's recompiled as a superblock
+const HOT_BLOCK_THRESHOLD: u32 = 64;
+const MAX_SUPERBLOCK_LENGTH: usize = 128;
+
+#[derive(Clone, Copy, PartialEq)]
+enum BlockState {
+	Uncompiled,
+	Compiled,
+	Superblock
+}
+
+// where the jumps of an instruction go, and the cycles they account for
+struct Jumps<'a> {
+	// followed by the superblock instead
+	next_address: Option<u16>,
+	cycles: u32,
+	table: &'a [u32; MEMORY_SIZE]
+}
 
 pub struct Recompiler {
-	code_cache: CodeCache
+	code_cache: CodeCache,
+	execution_counts: [u32; MEMORY_SIZE],
+	block_states: [BlockState; MEMORY_SIZE],
+	chip8_address: usize,
+	quirks: Quirks,
+	// blocks end before the addresses where the debugger may stop
+	stops: BTreeSet<u16>,
+	// generation of the debugger the stops were taken from
+	#[cfg(feature="debugger")]
+	stops_generation: Option<u32>,
+	// blocks at the stops, left out of the jump table so that jumps to them return to the debugger
+	detached_blocks: BTreeMap<u16, u32>,
+	// one instruction blocks for stepping, their jumps leave the code cache, with their dependencies
+	single_instruction_blocks: BTreeMap<u16, (u32, Vec<u16>)>,
+	// addresses of the instructions the blocks of the jump table and the detached ones depend on, by block
+	block_dependencies: BTreeMap<u16, Vec<u16>>
 }
 
 impl Recompiler {
-	pub fn new(register_pc: &u16) -> Recompiler {
+	pub fn new(chip8: &Chip8) -> Recompiler {
 		Recompiler {
-			code_cache: CodeCache::new(register_pc)
+			code_cache: CodeCache::new(&chip8.register_pc),
+			execution_counts: [0; MEMORY_SIZE],
+			block_states: [BlockState::Uncompiled; MEMORY_SIZE],
+			chip8_address: chip8 as *const Chip8 as usize,
+			quirks: chip8.quirks,
+			stops: BTreeSet::new(),
+			#[cfg(feature="debugger")]
+			stops_generation: None,
+			detached_blocks: BTreeMap::new(),
+			single_instruction_blocks: BTreeMap::new(),
+			block_dependencies: BTreeMap::new()
+		}
+	}
+
+	pub fn is_compiled_for(&self, chip8: &Chip8) -> bool {
+		self.chip8_address == chip8 as *const Chip8 as usize && self.quirks == chip8.quirks
+	}
+
+	// the compiled blocks don't end at the new stops, they're all thrown away
+	#[cfg(feature="debugger")]
+	pub fn set_stops(&mut self, stops: BTreeSet<u16>, generation: u32) {
+		self.stops_generation = Some(generation);
+		if stops != self.stops {
+			self.stops = stops;
+			self.invalidate();
+		}
+	}
+
+	#[cfg(feature="debugger")]
+	pub fn stops_generation(&self) -> Option<u32> {
+		self.stops_generation
+	}
+
+	// after the memory was edited from outside the emulated code
+	pub fn invalidate(&mut self) {
+		self.code_cache.clear();
+		self.execution_counts = [0; MEMORY_SIZE];
+		self.block_states = [BlockState::Uncompiled; MEMORY_SIZE];
+		self.detached_blocks.clear();
+		self.single_instruction_blocks.clear();
+		self.block_dependencies.clear();
+	}
+
+	// after the memory was replaced by a save state, only the blocks depending on changed instructions are recompiled
+	pub fn invalidate_changed_code(&mut self, previous_memory: &[u8], memory: &[u8]) {
+		let changed = |dependencies: &Vec<u16>| dependencies.iter().any(|&address| {
+			let instruction = address as usize..address as usize + 2;
+			previous_memory.get(instruction.clone()) != memory.get(instruction)
+		});
+
+		let stale_blocks: Vec<u16> = self.block_dependencies.iter()
+			.filter(|&(_, dependencies)| changed(dependencies))
+			.map(|(&start, _)| start)
+			.collect();
+		for start in stale_blocks {
+			let address = start as usize;
+			self.code_cache.x86_block_addresses[address] = self.code_cache.stub_addresses[address];
+			self.execution_counts[address] = 0;
+			self.block_states[address] = BlockState::Uncompiled;
+			self.detached_blocks.remove(&start);
+			self.block_dependencies.remove(&start);
 		}
+		self.single_instruction_blocks.retain(|_, block| !changed(&block.1));
 	}
 
-	pub fn execute_next_code_block(&mut self, chip8: &Chip8) {
-		let code_block = self.recompile_next_code_block(chip8);
-		self.code_cache.insert(chip8.register_pc, code_block);
+	fn is_stop(&self, address: u16) -> bool {
+		self.stops.contains(&address)
+	}
+
+	// returns after the instruction at PC, or when it's waiting for the next frame
+	// the code writes to the Chip8 behind the compiler's back, so it's borrowed mutably
+	pub fn execute_next_instruction(&mut self, chip8: &mut Chip8) {
+		let x86_address = match self.single_instruction_blocks.get(&chip8.register_pc).map(|block| block.0) {
+			Some(x86_address) => x86_address,
+			None => {
+				let (code_block, dependencies) = self.compile(chip8, false, true);
+				let x86_address = self.code_cache.append(code_block);
+				self.single_instruction_blocks.insert(chip8.register_pc, (x86_address, dependencies));
+				x86_address
+			}
+		};
+		self.code_cache.execute_at(x86_address);
+	}
+
+	// returns when the code reaches an uncompiled block, a block becomes hot, a stop, or the cycles to run are spent
+	pub fn execute_next_code_block(&mut self, chip8: &mut Chip8) {
+		if self.is_stop(chip8.register_pc) {
+			let x86_address = match self.detached_blocks.get(&chip8.register_pc).cloned() {
+				Some(x86_address) => x86_address,
+				None => {
+					let (code_block, dependencies) = self.compile(chip8, false, false);
+					let x86_address = self.code_cache.append(code_block);
+					self.detached_blocks.insert(chip8.register_pc, x86_address);
+					self.block_dependencies.insert(chip8.register_pc, dependencies);
+					x86_address
+				}
+			};
+			self.code_cache.execute_at(x86_address);
+			return;
+		}
+
+		let address = chip8.register_pc as usize;
+		let superblock = self.execution_counts[address] >= HOT_BLOCK_THRESHOLD;
+		let block_state = if superblock { BlockState::Superblock } else { BlockState::Compiled };
+
+		if self.block_states[address] != block_state {
+			let (code_block, dependencies) = self.compile(chip8, superblock, false);
+			self.code_cache.insert(chip8.register_pc, code_block);
+			self.block_dependencies.insert(chip8.register_pc, dependencies);
+			self.block_states[address] = block_state;
+		}
 		self.code_cache.execute(chip8.register_pc);
 	}
 
-	fn emit_call_refresh(code_emitter: &mut CodeEmitter, chip8: &Chip8) {
+	// the cache starts over when it's full, blocks are compiled again as they run
+	fn compile(&mut self, chip8: &Chip8, superblock: bool, single_instruction: bool) -> (Vec<u8>, Vec<u16>) {
+		let compiled = self.recompile_next_code_block(chip8, superblock, single_instruction);
+		if !self.code_cache.has_room(compiled.0.len()) {
+			self.invalidate();
+		}
+		compiled
+	}
+
+	fn emit_call_refresh(code_emitter: &mut CodeEmitter, chip8: &Chip8, cycles: u32) {
+		code_emitter.push_imm32(cycles);
 		code_emitter.push_imm32(chip8 as *const Chip8 as u32);
 		code_emitter.mov_imm_to_eax(Chip8::refresh as u32);
 		code_emitter.call_eax();
 	}
 
-	fn recompile_next_code_block(&self, chip8: &Chip8) -> Vec<u8> {
-		let mut code_emitter = CodeEmitter::new();
+	// the waiting instruction counts in the next frame, as in the interpreters
+	fn emit_call_wait_next_frame(code_emitter: &mut CodeEmitter, chip8: &Chip8, cycles: u32) {
+		code_emitter.push_imm32(cycles - 1);
+		code_emitter.push_imm32(chip8 as *const Chip8 as u32);
+		code_emitter.mov_imm_to_eax(Chip8::wait_next_frame as u32);
+		code_emitter.call_eax();
+		Recompiler::emit_call_refresh(code_emitter, chip8, 1);
+	}
+
+	fn emit_call_skip_to_next_frame(code_emitter: &mut CodeEmitter, chip8: &Chip8, cycles: u32) {
+		code_emitter.push_imm32(cycles - 1);
+		code_emitter.push_imm32(chip8 as *const Chip8 as u32);
+		code_emitter.mov_imm_to_eax(Chip8::skip_to_next_frame as u32);
+		code_emitter.call_eax();
+		Recompiler::emit_call_refresh(code_emitter, chip8, 1);
+	}
+
+	// returns to the caller of the code cache when refresh returned false, PC must be up to date
+	fn emit_exit_when_stopped(code_emitter: &mut CodeEmitter, chip8: &Chip8, register_pc: Option<u16>) {
+		code_emitter.cmp_al_with_imm(0);
+		let branch = code_emitter.jne_forward();
+		if let Some(register_pc) = register_pc {
+			code_emitter.mov_imm_to_m16(register_pc, &chip8.register_pc);
+		}
+		code_emitter.ret();
+		code_emitter.patch_forward(branch);
+	}
+
+	// leaves a block that doesn't end with a jump, to come back at the address
+	fn emit_exit_to(code_emitter: &mut CodeEmitter, chip8: &Chip8, register_pc: u16, cycles: u32) {
+		Recompiler::emit_call_refresh(code_emitter, chip8, cycles);
+		code_emitter.mov_imm_to_m16(register_pc, &chip8.register_pc);
+		code_emitter.ret();
+	}
+
+	// skips the load when AL already holds the register
+	fn emit_load_v_to_al(code_emitter: &mut CodeEmitter, al_registers: &mut u16, chip8: &Chip8, register: usize) {
+		if *al_registers & (1 << register) == 0 {
+			code_emitter.mov_m_to_al(&chip8.register_v[register]);
+			*al_registers = 1 << register;
+		}
+	}
+
+	fn emit_execution_counter(&self, code_emitter: &mut CodeEmitter, chip8: &Chip8) {
+		let execution_count = &self.execution_counts[chip8.register_pc as usize];
+		code_emitter.add_imm_to_m32(1, execution_count);
+		code_emitter.cmp_imm_with_m32(HOT_BLOCK_THRESHOLD, execution_count);
+		let branch = code_emitter.jne_forward();
+
+		// exit to the recompiler
+		code_emitter.mov_imm_to_m16(chip8.register_pc, &chip8.register_pc);
+		code_emitter.ret();
+		code_emitter.patch_forward(branch);
+	}
+
+	// superblocks follow 1NNN, 2NNN and 00EE, using the stack as recorded when they're formed
+	fn decode_next_code_block(&self, chip8: &Chip8, superblock: bool, single_instruction: bool) -> Vec<Instruction> {
+		let mut block: Vec<Instruction> = Vec::new();
 		let mut register_pc = chip8.register_pc;
+		let mut stack: Vec<u16> = chip8.stack.iter().take(chip8.register_sp.wrapping_add(1) as usize).cloned().collect();
 
 		loop {
-			let high_byte = chip8.memory[register_pc as usize];
-			let low_byte = chip8.memory[register_pc as usize + 1];
-			let opcode = (high_byte >> 4, high_byte & 0x0F, low_byte >> 4, low_byte & 0x0F);
-			let nnn = ((high_byte as u16 & 0x0F) << 8) | low_byte as u16;
-			let x = high_byte as usize & 0x0F;
-			let y = low_byte as usize >> 4;
-
+			let instruction = Instruction::decode(&chip8.memory, register_pc, &chip8.quirks);
+			let conditional = block.last().is_some_and(|previous| previous.is_skip());
 			register_pc += 2;
+			block.push(instruction);
 
-			match opcode {
-				(0x0, 0x0, 0xE, 0x0) => {
-					code_emitter.push_imm32(&chip8.display as *const Display as u32);
-					code_emitter.mov_imm_to_eax(Display::clear as u32);
-					code_emitter.call_eax();
-				},
-				(0x0, 0x0, 0xE, 0xE) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.movzx_m8_to_ecx(&chip8.register_sp);
-					code_emitter.sub_imm_to_m8(1, &chip8.register_sp);
-					code_emitter.mov_imm_to_edi(&chip8.stack[0] as *const u16 as u32);
-					code_emitter.movzx_m16_to_ecx_edi2ecx();
-
-					// jump to next block
-					code_emitter.mov_imm_to_edi(&self.code_cache.x86_block_addresses[0] as *const u32 as u32);
-					code_emitter.mov_m_to_eax_edi4ecx();
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0x1, ..) => { 
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
+			if single_instruction || self.is_stop(register_pc) {
+				return block;
+			}
 
-					// jump to next block
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[nnn as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
+			// a skipped jump doesn't end the block
+			if !instruction.is_jump() || conditional {
+				continue;
+			}
+
+			if !superblock {
+				return block;
+			}
+
+			let next_address = match instruction.opcode {
+				(0x0, 0x0, 0xE, 0xE) => stack.pop(),
+				(0x1, ..) => Some(instruction.nnn),
 				(0x2, ..) => {
-					code_emitter.add_imm_to_m8(1, &chip8.register_sp);
-					code_emitter.movzx_m8_to_ecx(&chip8.register_sp);
-					code_emitter.mov_imm_to_edi(&chip8.stack[0] as *const u16 as u32);
-					code_emitter.mov_imm_to_m16_edi2ecx(register_pc);
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-
-					// jump to next block
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[nnn as usize]);
-					code_emitter.jmp_eax();
-					break;
+					stack.push(register_pc);
+					Some(instruction.nnn)
 				},
-				(0x3, ..) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.cmp_imm_with_m8(low_byte, &chip8.register_v[x]);
-					code_emitter.jne(7);
+				_ => None
+			};
 
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
-					code_emitter.jmp_eax();
+			match next_address {
+				Some(address) if block.len() < MAX_SUPERBLOCK_LENGTH && block.iter().all(|decoded| decoded.address != address) && !self.is_stop(address) => register_pc = address,
+				_ => return block
+			}
+		}
+	}
 
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0x4, ..) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.cmp_imm_with_m8(low_byte, &chip8.register_v[x]);
-					code_emitter.je(7);
+	// returns the code and the addresses of the instructions it depends on
+	fn recompile_next_code_block(&self, chip8: &Chip8, superblock: bool, single_instruction: bool) -> (Vec<u8>, Vec<u16>) {
+		let block = self.decode_next_code_block(chip8, superblock, single_instruction);
+		let hints = optimizer::optimize(&block);
+		let mut code_emitter = CodeEmitter::new();
 
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
-					code_emitter.jmp_eax();
-					
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0x5, _, _, 0x0) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.mov_m_to_al(&chip8.register_v[x]);
-					code_emitter.cmp_m_with_al(&chip8.register_v[y]);
-					code_emitter.jne(7);
-
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
+		// detached and single instruction blocks aren't entered through the jump table, counting them is pointless
+		if !superblock && !single_instruction && !self.is_stop(chip8.register_pc) {
+			self.emit_execution_counter(&mut code_emitter, chip8);
+		}
+
+		// single instruction blocks jump to the stubs, which return to the caller
+		let jump_table = if single_instruction { &self.code_cache.stub_addresses } else { &self.code_cache.x86_block_addresses };
+
+		// bit mask of the V registers whose value is currently held in AL
+		let mut al_registers: u16 = 0;
+
+		// forward branch of the previous skip instruction, and AL at that point
+		let mut pending_skip: Option<(usize, u16)> = None;
+
+		// instructions since the last refresh, the skipped ones are taken back when the code runs
+		let mut cycles = 0;
+
+		// blocks cut short at a stop need a way out
+		let mut ends_with_jump = false;
+
+		for (index, (instruction, hint)) in block.iter().zip(hints.iter()).enumerate() {
+			let skip = pending_skip.take();
+			cycles += 1;
+
+			// jumps followed by the superblock
+			let next_address = match block.get(index + 1) {
+				Some(next) if instruction.is_jump() && skip.is_none() => Some(next.address),
+				_ => None
+			};
+
+			let jumps = Jumps { next_address, cycles, table: jump_table };
+			if let Some(branch) = self.emit_instruction(&mut code_emitter, &mut al_registers, chip8, instruction, hint, &jumps) {
+				pending_skip = Some((branch, al_registers));
+			}
+
+			ends_with_jump = instruction.is_jump() && skip.is_none();
+			if ends_with_jump {
+				cycles = 0;
+			}
+
+			// the skipped instruction is counted in cycles, it's taken back when it doesn't run
+			if let Some((branch, skipped_al_registers)) = skip {
+				let executed = code_emitter.jmp_forward();
+				code_emitter.patch_forward(branch);
+				code_emitter.add_imm_to_m32(1, &chip8.skipped_cycles);
+				code_emitter.patch_forward(executed);
+				al_registers &= skipped_al_registers;
+			}
+		}
+
+		if !ends_with_jump {
+			let next_address = block.last().map_or(chip8.register_pc, |last| last.address + 2);
+			Recompiler::emit_exit_to(&mut code_emitter, chip8, next_address, cycles);
+			// the last instruction skips the one at the stop
+			if let Some((branch, _)) = pending_skip {
+				code_emitter.patch_forward(branch);
+				Recompiler::emit_exit_to(&mut code_emitter, chip8, next_address + 2, cycles);
+			}
+		}
+
+		// the instructions compiled, and the loops the jumps were found idling in
+		let mut dependencies: Vec<u16> = block.iter().map(|decoded| decoded.address).collect();
+		for decoded in block.iter() {
+			if let (0x1, ..) = decoded.opcode {
+				dependencies.extend_from_slice(&[decoded.nnn, decoded.nnn + 2, decoded.nnn + 4]);
+			}
+		}
+		(code_emitter.raw_code, dependencies)
+	}
+
+	// returns the position of the forward branch to patch for skip instructions
+	fn emit_instruction(&self, code_emitter: &mut CodeEmitter, al_registers: &mut u16, chip8: &Chip8, instruction: &Instruction, hint: &Hint, jumps: &Jumps) -> Option<usize> {
+		let Jumps { next_address, cycles, table: jump_table } = *jumps;
+		let register_pc = instruction.address + 2;
+		let low_byte = instruction.low_byte;
+		let nnn = instruction.nnn;
+		let x = instruction.x;
+		let y = instruction.y;
+
+		if hint.dead {
+			return None;
+		}
+
+		if let Some(result) = hint.result {
+			code_emitter.mov_imm_to_m8(result, &chip8.register_v[x]);
+			*al_registers &= !(1 << x);
+			if !hint.dead_flag {
+				if let Some(flag) = hint.flag {
+					code_emitter.mov_imm_to_m8(flag, &chip8.register_v[0xF]);
+					*al_registers &= !0x8000;
+				}
+			}
+			return None;
+		}
+
+		match instruction.opcode {
+			(0x0, 0x0, 0xE, 0x0) => {
+				code_emitter.push_imm32(&chip8.display as *const Display as u32);
+				code_emitter.mov_imm_to_eax(Display::clear as u32);
+				code_emitter.call_eax();
+				*al_registers = 0;
+			},
+			(0x0, 0x0, 0xE, 0xE) => {
+				code_emitter.movzx_m8_to_ecx(&chip8.register_sp);
+				code_emitter.sub_imm_to_m8(1, &chip8.register_sp);
+				code_emitter.mov_imm_to_edi(&chip8.stack[0] as *const u16 as u32);
+				code_emitter.movzx_m16_to_ecx_edi2ecx();
+				code_emitter.mov_cx_to_m(&chip8.register_pc);
+				Recompiler::emit_call_refresh(code_emitter, chip8, cycles);
+				Recompiler::emit_exit_when_stopped(code_emitter, chip8, None);
+				code_emitter.movzx_m16_to_ecx(&chip8.register_pc);
+				*al_registers = 0;
+
+				// guard on the recorded return address
+				let guard = next_address.map(|address| {
+					code_emitter.cmp_imm_with_ecx(address as u32);
+					code_emitter.je_forward()
+				});
+
+				// jump to next block
+				code_emitter.mov_imm_to_edi(&jump_table[0] as *const u32 as u32);
+				code_emitter.mov_m_to_eax_edi4ecx();
+				code_emitter.jmp_eax();
+
+				if let Some(branch) = guard {
+					code_emitter.patch_forward(branch);
+				}
+			},
+			(0x1, ..) => { 
+				if chip8.is_idle_loop(nnn) {
+					Recompiler::emit_call_wait_next_frame(code_emitter, chip8, cycles);
+				} else {
+					Recompiler::emit_call_refresh(code_emitter, chip8, cycles);
+				}
+				Recompiler::emit_exit_when_stopped(code_emitter, chip8, Some(nnn));
+				*al_registers = 0;
+
+				// jump to next block
+				if next_address.is_none() {
+					code_emitter.mov_m_to_eax(&jump_table[nnn as usize]);
 					code_emitter.jmp_eax();
-					
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
+				}
+			},
+			(0x2, ..) => {
+				code_emitter.add_imm_to_m8(1, &chip8.register_sp);
+				code_emitter.movzx_m8_to_ecx(&chip8.register_sp);
+				code_emitter.mov_imm_to_edi(&chip8.stack[0] as *const u16 as u32);
+				code_emitter.mov_imm_to_m16_edi2ecx(register_pc);
+				Recompiler::emit_call_refresh(code_emitter, chip8, cycles);
+				Recompiler::emit_exit_when_stopped(code_emitter, chip8, Some(nnn));
+				*al_registers = 0;
+
+				// jump to next block
+				if next_address.is_none() {
+					code_emitter.mov_m_to_eax(&jump_table[nnn as usize]);
 					code_emitter.jmp_eax();
-					break;
-				},
-				(0x6, ..) => code_emitter.mov_imm_to_m8(low_byte, &chip8.register_v[x]),
-				(0x7, ..) => code_emitter.add_imm_to_m8(low_byte, &chip8.register_v[x]),
-				(0x8, _, _, 0x0) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x1) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.or_m_al(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x2) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.and_m_al(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x3) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.xor_m_al(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x4) => {
-					code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
-					code_emitter.movzx_m_to_cx(&chip8.register_v[y]);
-					code_emitter.add_cx_to_ax();
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				}
+			},
+			(0x3, ..) => {
+				code_emitter.cmp_imm_with_m8(low_byte, &chip8.register_v[x]);
+				return Some(code_emitter.je_forward());
+			},
+			(0x4, ..) => {
+				code_emitter.cmp_imm_with_m8(low_byte, &chip8.register_v[x]);
+				return Some(code_emitter.jne_forward());
+			},
+			(0x5, _, _, 0x0) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, x);
+				code_emitter.cmp_m_with_al(&chip8.register_v[y]);
+				return Some(code_emitter.je_forward());
+			},
+			(0x6, ..) => {
+				code_emitter.mov_imm_to_m8(low_byte, &chip8.register_v[x]);
+				*al_registers &= !(1 << x);
+			},
+			(0x7, ..) => {
+				code_emitter.add_imm_to_m8(low_byte, &chip8.register_v[x]);
+				*al_registers &= !(1 << x);
+			},
+			(0x8, _, _, 0x0) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers |= 1 << x;
+			},
+			(0x8, _, _, 0x1) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				code_emitter.or_m_al(&chip8.register_v[x]);
+				*al_registers &= !(1 << x);
+			},
+			(0x8, _, _, 0x2) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				code_emitter.and_m_al(&chip8.register_v[x]);
+				*al_registers &= !(1 << x);
+			},
+			(0x8, _, _, 0x3) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				code_emitter.xor_m_al(&chip8.register_v[x]);
+				*al_registers &= !(1 << x);
+			},
+			(0x8, _, _, 0x4) => {
+				code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
+				code_emitter.movzx_m_to_cx(&chip8.register_v[y]);
+				code_emitter.add_cx_to_ax();
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+				if !hint.dead_flag {
 					code_emitter.cmp_ax_with_imm(0xFF);
 					code_emitter.seta_m(&chip8.register_v[0xF]);
-				},
-				(0x8, _, _, 0x5) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[x]);
-					code_emitter.sub_m_to_al(&chip8.register_v[y]);
+					*al_registers &= !0x8000;
+				}
+			},
+			(0x8, _, _, 0x5) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, x);
+				code_emitter.sub_m_to_al(&chip8.register_v[y]);
+				if !hint.dead_flag {
 					code_emitter.setae_m(&chip8.register_v[0xF]);
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x6) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
+				}
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0x8, _, _, 0x6) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				if !hint.dead_flag {
 					code_emitter.mov_al_to_cl();
 					code_emitter.and_cl_imm(1);
 					code_emitter.mov_cl_to_m(&chip8.register_v[0xF]);
-					code_emitter.shr_al();
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0x7) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.sub_m_to_al(&chip8.register_v[x]);
+				}
+				code_emitter.shr_al();
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0x8, _, _, 0x7) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				code_emitter.sub_m_to_al(&chip8.register_v[x]);
+				if !hint.dead_flag {
 					code_emitter.setae_m(&chip8.register_v[0xF]);
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0x8, _, _, 0xE) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[y]);
-					code_emitter.shr_al_imm(7);
-					code_emitter.mov_al_to_m(&chip8.register_v[0xF]);
-					code_emitter.add_al_to_al();
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0x9, _, _, 0x0) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.mov_m_to_al(&chip8.register_v[x]);
-					code_emitter.cmp_m_with_al(&chip8.register_v[y]);
-					code_emitter.je(7);
-
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
-					code_emitter.jmp_eax();
-					
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0xA, ..) => code_emitter.mov_imm_to_m16(nnn, &chip8.register_i),
-				(0xB, ..) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.movzx_m8_to_ecx(&chip8.register_v[0]);
-					code_emitter.add_imm_to_ecx(nnn as u32);
-
-					// jump to next block
-					code_emitter.mov_imm_to_edi(&self.code_cache.x86_block_addresses[0] as *const u32 as u32);
-					code_emitter.mov_m_to_eax_edi4ecx();
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0xC, ..) => {
-					code_emitter.rdrand_ax();
-					code_emitter.and_al_imm(low_byte);
-					code_emitter.mov_al_to_m(&chip8.register_v[x])
-				},
-				(0xD, _, _, n) => {
-					code_emitter.push_imm32(n as u32);
-					code_emitter.movzx_m16_to_eax(&chip8.register_i);
-					code_emitter.add_imm_to_eax(&chip8.memory[0] as *const u8 as u32);
-					code_emitter.push_eax();
-					code_emitter.movzx_m8_to_eax(&chip8.register_v[y]);
-					code_emitter.push_eax();
-					code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
-					code_emitter.push_eax();
-					code_emitter.push_imm32(&chip8.display as *const Display as u32);
-					code_emitter.mov_imm_to_eax(Display::draw_sprite as u32);
-					code_emitter.call_eax();
-					code_emitter.mov_al_to_m(&chip8.register_v[0xF]);
-				},
-				(0xE, _, 0x9, 0xE) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
-					code_emitter.push_eax();
-					code_emitter.push_imm32(&chip8.keyboard as *const Keyboard as u32);
-					code_emitter.mov_imm_to_eax(Keyboard::is_pressed as u32);
-					code_emitter.call_eax();
-					code_emitter.cmp_al_with_imm(1);
-					code_emitter.jne(7);
-					
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
-					code_emitter.jmp_eax();
-					
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0xE, _, 0xA, 0x1) => {
-					Recompiler::emit_call_refresh(&mut code_emitter, chip8);
-					code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
-					code_emitter.push_eax();
-					code_emitter.push_imm32(&chip8.keyboard as *const Keyboard as u32);
-					code_emitter.mov_imm_to_eax(Keyboard::is_pressed as u32);
-					code_emitter.call_eax();
-					code_emitter.cmp_al_with_imm(1);
-					code_emitter.je(7);
-
-					// jump to next block (PC+2)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize + 2]);
-					code_emitter.jmp_eax();
-					
-					// jump to next block (PC)
-					code_emitter.mov_m_to_eax(&self.code_cache.x86_block_addresses[register_pc as usize]);
-					code_emitter.jmp_eax();
-					break;
-				},
-				(0xF, _, 0x0, 0x7) => {
-					code_emitter.mov_m_to_al(&chip8.register_dt);
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0xF, _, 0x0, 0xA) => {
-					code_emitter.push_imm32(&chip8.keyboard as *const Keyboard as u32);
-					code_emitter.mov_imm_to_eax(Keyboard::wait_key_press as u32);
-					code_emitter.call_eax();
-					code_emitter.mov_al_to_m(&chip8.register_v[x]);
-				},
-				(0xF, _, 0x1, 0x5) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[x]);
-					code_emitter.mov_al_to_m(&chip8.register_dt);
-				},
-				(0xF, _, 0x1, 0x8) => {
-					code_emitter.mov_m_to_al(&chip8.register_v[x]);
-					code_emitter.mov_al_to_m(&chip8.register_st);
-				},
-				(0xF, _, 0x1, 0xE) => {
-					code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
-					code_emitter.add_ax_to_m(&chip8.register_i);
-				},
-				(0xF, _, 0x2, 0x9) => {
-					code_emitter.mov_imm_to_al(5);
-					code_emitter.mul_m8(&chip8.register_v[x]);
-					code_emitter.mov_ax_to_m(&chip8.register_i);
-				},
-				(0xF, _, 0x3, 0x3) => {
-					code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
-					code_emitter.mov_imm_to_dl(100);
-					code_emitter.div_dl();
-					code_emitter.movzx_m16_to_ecx(&chip8.register_i);
-					code_emitter.mov_imm_to_edi(&chip8.memory[0] as *const u8 as u32);
-					code_emitter.mov_al_to_m_ediecx();
-					code_emitter.movzx_ah_to_ax();
-					code_emitter.mov_imm_to_dl(10);
-					code_emitter.div_dl();
-					code_emitter.mov_imm_to_edi(&chip8.memory[1] as *const u8 as u32);
+				}
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0x8, _, _, 0xE) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, y);
+				if !hint.dead_flag {
+					code_emitter.mov_al_to_cl();
+					code_emitter.shr_cl_imm(7);
+					code_emitter.mov_cl_to_m(&chip8.register_v[0xF]);
+				}
+				code_emitter.add_al_to_al();
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0x9, _, _, 0x0) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, x);
+				code_emitter.cmp_m_with_al(&chip8.register_v[y]);
+				return Some(code_emitter.jne_forward());
+			},
+			(0xA, ..) => code_emitter.mov_imm_to_m16(nnn, &chip8.register_i),
+			(0xB, ..) => {
+				let offset = if chip8.quirks.jump_vx { x } else { 0 };
+				code_emitter.movzx_m8_to_ecx(&chip8.register_v[offset]);
+				code_emitter.add_imm_to_ecx(nnn as u32);
+				code_emitter.mov_cx_to_m(&chip8.register_pc);
+				Recompiler::emit_call_refresh(code_emitter, chip8, cycles);
+				Recompiler::emit_exit_when_stopped(code_emitter, chip8, None);
+				code_emitter.movzx_m16_to_ecx(&chip8.register_pc);
+				*al_registers = 0;
+
+				// jump to next block
+				code_emitter.mov_imm_to_edi(&jump_table[0] as *const u32 as u32);
+				code_emitter.mov_m_to_eax_edi4ecx();
+				code_emitter.jmp_eax();
+			},
+			(0xC, ..) => {
+				code_emitter.push_imm32(chip8 as *const Chip8 as u32);
+				code_emitter.mov_imm_to_eax(Chip8::random_byte as u32);
+				code_emitter.call_eax();
+				code_emitter.and_al_imm(low_byte);
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0xD, _, _, n) => {
+				// like Display::draw_sprite with SSE2: XMM1 and XMM2 hold the shifts rotating
+				// the sprite rows right by VX, XMM7 the pixels they erased
+				let rows = chip8.display.rows().as_ptr() as u32;
+				code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
+				code_emitter.and_imm_to_eax(DISPLAY_WIDTH as u32 - 1);
+				code_emitter.movd_eax_to_xmm(1);
+				code_emitter.neg_eax();
+				code_emitter.add_imm_to_eax(DISPLAY_WIDTH as u32);
+				code_emitter.movd_eax_to_xmm(2);
+				code_emitter.movzx_m8_to_ecx(&chip8.register_v[y]);
+				code_emitter.movzx_m16_to_edx(&chip8.register_i);
+				code_emitter.pxor(7, 7);
+				for row in 0..n as u32 {
+					code_emitter.movzx_m8_to_eax_edx(&chip8.memory[0] as *const u8 as u32 + row);
+					code_emitter.movd_eax_to_xmm(0);
+					code_emitter.psllq_imm(0, DISPLAY_WIDTH as u8 - 8);
+					code_emitter.movdqa_xmm_to_xmm(3, 0);
+					code_emitter.psrlq(0, 1);
+					code_emitter.psllq(3, 2);
+					code_emitter.por(0, 3);
+					code_emitter.lea_eax_ecx(row);
+					code_emitter.and_imm_to_eax(DISPLAY_HEIGHT as u32 - 1);
+					code_emitter.movq_m_eax8_to_xmm(3, rows);
+					code_emitter.movdqa_xmm_to_xmm(4, 3);
+					code_emitter.pand(4, 0);
+					code_emitter.por(7, 4);
+					code_emitter.pxor(3, 0);
+					code_emitter.movq_xmm_to_m_eax8(3, rows);
+				}
+				code_emitter.movd_xmm_to_eax(7);
+				code_emitter.psrlq_imm(7, 32);
+				code_emitter.movd_xmm_to_ecx(7);
+				code_emitter.or_ecx_to_eax();
+				code_emitter.setne_al();
+				code_emitter.mov_al_to_m(&chip8.register_v[0xF]);
+				*al_registers = 0x8000;
+			},
+			(0xE, _, 0x9, 0xE) => {
+				code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
+				code_emitter.push_eax();
+				code_emitter.push_imm32(&chip8.keyboard as *const Keyboard as u32);
+				code_emitter.mov_imm_to_eax(Keyboard::is_pressed as u32);
+				code_emitter.call_eax();
+				*al_registers = 0;
+				code_emitter.cmp_al_with_imm(1);
+				return Some(code_emitter.je_forward());
+			},
+			(0xE, _, 0xA, 0x1) => {
+				code_emitter.movzx_m8_to_eax(&chip8.register_v[x]);
+				code_emitter.push_eax();
+				code_emitter.push_imm32(&chip8.keyboard as *const Keyboard as u32);
+				code_emitter.mov_imm_to_eax(Keyboard::is_pressed as u32);
+				code_emitter.call_eax();
+				*al_registers = 0;
+				code_emitter.cmp_al_with_imm(1);
+				return Some(code_emitter.jne_forward());
+			},
+			(0xF, _, 0x0, 0x7) => {
+				code_emitter.mov_m_to_al(&chip8.register_dt);
+				code_emitter.mov_al_to_m(&chip8.register_v[x]);
+				*al_registers = 1 << x;
+			},
+			(0xF, _, 0x0, 0xA) => {
+				code_emitter.push_imm32(x as u32);
+				code_emitter.push_imm32(chip8 as *const Chip8 as u32);
+				code_emitter.mov_imm_to_eax(Chip8::wait_key_release as u32);
+				code_emitter.call_eax();
+				*al_registers = 0;
+
+				// still waiting, leave the block and come back to FX0A on the next frame
+				code_emitter.cmp_al_with_imm(0);
+				let branch = code_emitter.jne_forward();
+				code_emitter.mov_imm_to_m16(instruction.address, &chip8.register_pc);
+				Recompiler::emit_call_skip_to_next_frame(code_emitter, chip8, cycles);
+				code_emitter.ret();
+				code_emitter.patch_forward(branch);
+			},
+			(0xF, _, 0x1, 0x5) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, x);
+				code_emitter.mov_al_to_m(&chip8.register_dt);
+			},
+			(0xF, _, 0x1, 0x8) => {
+				Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, x);
+				code_emitter.mov_al_to_m(&chip8.register_st);
+			},
+			(0xF, _, 0x1, 0xE) => {
+				code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
+				code_emitter.add_ax_to_m(&chip8.register_i);
+				*al_registers = 1 << x;
+			},
+			(0xF, _, 0x2, 0x9) => {
+				code_emitter.mov_imm_to_al(5);
+				code_emitter.mul_m8(&chip8.register_v[x]);
+				code_emitter.mov_ax_to_m(&chip8.register_i);
+				*al_registers = 0;
+			},
+			(0xF, _, 0x3, 0x3) => {
+				code_emitter.movzx_m_to_ax(&chip8.register_v[x]);
+				code_emitter.mov_imm_to_dl(100);
+				code_emitter.div_dl();
+				code_emitter.movzx_m16_to_ecx(&chip8.register_i);
+				code_emitter.mov_imm_to_edi(&chip8.memory[0] as *const u8 as u32);
+				code_emitter.mov_al_to_m_ediecx();
+				code_emitter.movzx_ah_to_ax();
+				code_emitter.mov_imm_to_dl(10);
+				code_emitter.div_dl();
+				code_emitter.mov_imm_to_edi(&chip8.memory[1] as *const u8 as u32);
+				code_emitter.mov_al_to_m_ediecx();
+				code_emitter.mov_imm_to_edi(&chip8.memory[2] as *const u8 as u32);
+				code_emitter.mov_ah_to_m_ediecx();
+				*al_registers = 0;
+			},
+			(0xF, _, 0x5, 0x5) => {
+				code_emitter.movzx_m16_to_ecx(&chip8.register_i);
+				for i in 0..(x + 1) {
+					Recompiler::emit_load_v_to_al(code_emitter, al_registers, chip8, i);
+					code_emitter.mov_imm_to_edi(&chip8.memory[i] as *const u8 as u32);
 					code_emitter.mov_al_to_m_ediecx();
-					code_emitter.mov_imm_to_edi(&chip8.memory[2] as *const u8 as u32);
-					code_emitter.mov_ah_to_m_ediecx();
-				},
-				(0xF, _, 0x5, 0x5) => {
-					code_emitter.movzx_m16_to_ecx(&chip8.register_i);
-					for i in 0..(x + 1) {
-						code_emitter.mov_m_to_al(&chip8.register_v[i]);
-						code_emitter.mov_imm_to_edi(&chip8.memory[i] as *const u8 as u32);
-						code_emitter.mov_al_to_m_ediecx();
-					}
+				}
+				if !chip8.quirks.load_store_keep_i {
 					code_emitter.add_imm_to_m16(x as u16 + 1, &chip8.register_i);
-				},
-				(0xF, _, 0x6, 0x5) => {
-					code_emitter.movzx_m16_to_ecx(&chip8.register_i);
-					for i in 0..(x + 1) {
-						code_emitter.mov_imm_to_edi(&chip8.memory[i] as *const u8 as u32);
-						code_emitter.mov_m_to_al_ediecx();
-						code_emitter.mov_al_to_m(&chip8.register_v[i]);
-					}
+				}
+			},
+			(0xF, _, 0x6, 0x5) => {
+				code_emitter.movzx_m16_to_ecx(&chip8.register_i);
+				for i in 0..(x + 1) {
+					code_emitter.mov_imm_to_edi(&chip8.memory[i] as *const u8 as u32);
+					code_emitter.mov_m_to_al_ediecx();
+					code_emitter.mov_al_to_m(&chip8.register_v[i]);
+				}
+				if !chip8.quirks.load_store_keep_i {
 					code_emitter.add_imm_to_m16(x as u16 + 1, &chip8.register_i);
-				},
-				_ => panic!("unknown opcode")
-			}
+				}
+				*al_registers = 1 << x;
+			},
+			_ => panic!("unknown opcode")
 		}
 
-		code_emitter.raw_code
+		None
 	}
 }
@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+// save states taken every interval frames, the newest whole and the older ones
+// as their difference with the next one, mostly runs of unchanged bytes
+pub struct Rewind {
+	pub interval: u32,
+	// snapshots kept, the oldest are dropped first
+	pub capacity: usize,
+	latest: Option<Vec<u8>>,
+	deltas: VecDeque<Vec<u8>>,
+	frames_since_snapshot: u32
+}
+
+impl Rewind {
+	pub fn new(interval: u32, capacity: usize) -> Rewind {
+		Rewind {
+			interval,
+			capacity,
+			latest: None,
+			deltas: VecDeque::new(),
+			frames_since_snapshot: 0
+		}
+	}
+
+	// counts a frame, true when a snapshot should be pushed
+	pub fn is_snapshot_due(&mut self) -> bool {
+		self.frames_since_snapshot += 1;
+		if self.frames_since_snapshot < self.interval {
+			return false;
+		}
+		self.frames_since_snapshot = 0;
+		true
+	}
+
+	pub fn push(&mut self, state: Vec<u8>) {
+		if let Some(latest) = self.latest.take() {
+			if latest.len() == state.len() {
+				self.deltas.push_back(encode_delta(&state, &latest));
+			} else {
+				self.deltas.clear();
+			}
+		}
+		self.latest = Some(state);
+
+		while self.deltas.len() + 1 > self.capacity && !self.deltas.is_empty() {
+			self.deltas.pop_front();
+		}
+	}
+
+	// the snapshot before the newest one, which is dropped, or the oldest once they're all gone
+	pub fn step_back(&mut self) -> Option<Vec<u8>> {
+		let delta = self.deltas.pop_back();
+		if let (Some(delta), Some(latest)) = (delta, self.latest.as_mut()) {
+			apply_delta(&delta, latest);
+		}
+		self.frames_since_snapshot = 0;
+		self.latest.clone()
+	}
+
+	pub fn len(&self) -> usize {
+		self.deltas.len() + self.latest.is_some() as usize
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.latest.is_none()
+	}
+
+	pub fn clear(&mut self) {
+		self.latest = None;
+		self.deltas.clear();
+	}
+}
+
+// the XOR of both states as runs of zeroes and of changed bytes, each run preceded by its u16 length
+fn encode_delta(state: &[u8], previous: &[u8]) -> Vec<u8> {
+	let mut delta = Vec::new();
+	let mut index = 0;
+	while index < state.len() {
+		let unchanged = state[index..].iter().zip(&previous[index..]).take(0xFFFF).take_while(|&(a, b)| a == b).count();
+		index += unchanged;
+		let changed = state[index..].iter().zip(&previous[index..]).take(0xFFFF).take_while(|&(a, b)| a != b).count();
+
+		delta.extend_from_slice(&[unchanged as u8, (unchanged >> 8) as u8, changed as u8, (changed >> 8) as u8]);
+		delta.extend(state[index..index + changed].iter().zip(&previous[index..index + changed]).map(|(a, b)| a ^ b));
+		index += changed;
+	}
+	delta
+}
+
+fn apply_delta(delta: &[u8], state: &mut [u8]) {
+	let (mut position, mut index) = (0, 0);
+	while position + 4 <= delta.len() {
+		let unchanged = delta[position] as usize | (delta[position + 1] as usize) << 8;
+		let changed = delta[position + 2] as usize | (delta[position + 3] as usize) << 8;
+		position += 4;
+		index += unchanged;
+		for (byte, &difference) in state[index..index + changed].iter_mut().zip(&delta[position..position + changed]) {
+			*byte ^= difference;
+		}
+		position += changed;
+		index += changed;
+	}
+}
@@ -0,0 +1,107 @@
+// save states start with this, then the version of their layout
+pub const MAGIC: &[u8; 4] = b"C8ST";
+// 2 added the random number generator
+pub const VERSION: u16 = 2;
+
+// little endian fields, in the order Chip8::save_state writes them
+pub struct StateWriter {
+	pub bytes: Vec<u8>
+}
+
+impl StateWriter {
+	pub fn new() -> StateWriter {
+		let mut writer = StateWriter {
+			bytes: Vec::new()
+		};
+		writer.write_bytes(MAGIC);
+		writer.write_u16(VERSION);
+		writer
+	}
+
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		self.bytes.extend_from_slice(bytes);
+	}
+
+	pub fn write_u8(&mut self, value: u8) {
+		self.bytes.push(value);
+	}
+
+	pub fn write_u16(&mut self, value: u16) {
+		self.write_u64_bytes(value as u64, 2);
+	}
+
+	pub fn write_u32(&mut self, value: u32) {
+		self.write_u64_bytes(value as u64, 4);
+	}
+
+	pub fn write_u64(&mut self, value: u64) {
+		self.write_u64_bytes(value, 8);
+	}
+
+	fn write_u64_bytes(&mut self, value: u64, count: usize) {
+		for i in 0..count {
+			self.bytes.push((value >> (i * 8)) as u8);
+		}
+	}
+}
+
+pub struct StateReader<'a> {
+	bytes: &'a [u8],
+	// layout version of the state being read
+	pub version: u16
+}
+
+impl<'a> StateReader<'a> {
+	pub fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, String> {
+		let mut reader = StateReader {
+			bytes,
+			version: 0
+		};
+		if reader.read_bytes(MAGIC.len())? != MAGIC {
+			return Err("not a save state".to_string());
+		}
+		reader.version = reader.read_u16()?;
+		if reader.version == 0 || reader.version > VERSION {
+			return Err(format!("save state version {} isn't supported, at most {} is", reader.version, VERSION));
+		}
+		Ok(reader)
+	}
+
+	pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+		if self.bytes.len() < count {
+			return Err("truncated save state".to_string());
+		}
+		let (bytes, rest) = self.bytes.split_at(count);
+		self.bytes = rest;
+		Ok(bytes)
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, String> {
+		Ok(self.read_bytes(1)?[0])
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, String> {
+		Ok(self.read_u64_bytes(2)? as u16)
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, String> {
+		Ok(self.read_u64_bytes(4)? as u32)
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, String> {
+		self.read_u64_bytes(8)
+	}
+
+	fn read_u64_bytes(&mut self, count: usize) -> Result<u64, String> {
+		let bytes = self.read_bytes(count)?;
+		Ok(bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64))
+	}
+
+	pub fn finish(&self) -> Result<(), String> {
+		if self.bytes.is_empty() {
+			Ok(())
+		} else {
+			Err(format!("{} unexpected bytes at the end of the save state", self.bytes.len()))
+		}
+	}
+}
@@ -0,0 +1,299 @@
+use chip8::Chip8;
+use chip8::MEMORY_SIZE;
+
+type Handler = fn(&mut ThreadedInterpreter, &mut Chip8, Entry);
+
+#[derive(Clone, Copy)]
+struct Entry {
+	handler: Handler,
+	x: u8,
+	low_byte: u8
+}
+
+impl Entry {
+	fn nnn(&self) -> u16 {
+		((self.x as u16) << 8) | self.low_byte as u16
+	}
+
+	fn x(&self) -> usize {
+		self.x as usize
+	}
+
+	fn y(&self) -> usize {
+		self.low_byte as usize >> 4
+	}
+}
+
+// decodes the instruction on first execution
+const UNDECODED: Entry = Entry {
+	handler: decode,
+	x: 0,
+	low_byte: 0
+};
+
+pub struct ThreadedInterpreter {
+	entries: [Entry; MEMORY_SIZE]
+}
+
+impl ThreadedInterpreter {
+	pub fn new() -> ThreadedInterpreter {
+		ThreadedInterpreter {
+			entries: [UNDECODED; MEMORY_SIZE]
+		}
+	}
+
+	pub fn execute_next_instruction(&mut self, chip8: &mut Chip8) {
+		let entry = self.entries[chip8.register_pc as usize];
+		chip8.register_pc += 2;
+		(entry.handler)(self, chip8, entry);
+	}
+
+	// instructions overlapping the stored bytes must be decoded again
+	fn invalidate(&mut self, start: usize, end: usize) {
+		let start = if start > 0 { start - 1 } else { 0 };
+		let end = if end < MEMORY_SIZE { end } else { MEMORY_SIZE };
+		for entry in &mut self.entries[start..end] {
+			*entry = UNDECODED;
+		}
+	}
+}
+
+fn decode(threaded_interpreter: &mut ThreadedInterpreter, chip8: &mut Chip8, _: Entry) {
+	let address = chip8.register_pc as usize - 2;
+	let high_byte = chip8.memory[address];
+	let low_byte = chip8.memory[address + 1];
+	let opcode = (high_byte >> 4, high_byte & 0x0F, low_byte >> 4, low_byte & 0x0F);
+
+	let handler: Handler = match opcode {
+		(0x0, 0x0, 0xE, 0x0) => clear_display,
+		(0x0, 0x0, 0xE, 0xE) => return_from_subroutine,
+		(0x1, ..) => jump,
+		(0x2, ..) => call_subroutine,
+		(0x3, ..) => skip_if_equal_imm,
+		(0x4, ..) => skip_if_not_equal_imm,
+		(0x5, _, _, 0x0) => skip_if_equal,
+		(0x6, ..) => load_imm,
+		(0x7, ..) => add_imm,
+		(0x8, _, _, 0x0) => load,
+		(0x8, _, _, 0x1) => or,
+		(0x8, _, _, 0x2) => and,
+		(0x8, _, _, 0x3) => xor,
+		(0x8, _, _, 0x4) => add,
+		(0x8, _, _, 0x5) => sub,
+		(0x8, _, _, 0x6) => shift_right,
+		(0x8, _, _, 0x7) => sub_reversed,
+		(0x8, _, _, 0xE) => shift_left,
+		(0x9, _, _, 0x0) => skip_if_not_equal,
+		(0xA, ..) => load_i,
+		(0xB, ..) => jump_v0,
+		(0xC, ..) => random,
+		(0xD, ..) => draw_sprite,
+		(0xE, _, 0x9, 0xE) => skip_if_pressed,
+		(0xE, _, 0xA, 0x1) => skip_if_not_pressed,
+		(0xF, _, 0x0, 0x7) => load_delay_timer,
+		(0xF, _, 0x0, 0xA) => wait_key_release,
+		(0xF, _, 0x1, 0x5) => set_delay_timer,
+		(0xF, _, 0x1, 0x8) => set_sound_timer,
+		(0xF, _, 0x1, 0xE) => add_to_i,
+		(0xF, _, 0x2, 0x9) => load_font,
+		(0xF, _, 0x3, 0x3) => store_bcd,
+		(0xF, _, 0x5, 0x5) => store_registers,
+		(0xF, _, 0x6, 0x5) => load_registers,
+		_ => panic!("unknown opcode")
+	};
+
+	let entry = Entry {
+		handler,
+		x: high_byte & 0x0F,
+		low_byte
+	};
+	threaded_interpreter.entries[address] = entry;
+	(entry.handler)(threaded_interpreter, chip8, entry);
+}
+
+fn clear_display(_: &mut ThreadedInterpreter, chip8: &mut Chip8, _: Entry) {
+	chip8.display.clear();
+}
+
+fn return_from_subroutine(_: &mut ThreadedInterpreter, chip8: &mut Chip8, _: Entry) {
+	chip8.register_pc = chip8.stack[chip8.register_sp as usize];
+	chip8.register_sp = chip8.register_sp.wrapping_sub(1);
+}
+
+fn jump(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_pc = entry.nnn();
+	if chip8.is_idle_loop(entry.nnn()) {
+		chip8.wait_next_frame(0);
+	}
+}
+
+fn call_subroutine(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_sp = chip8.register_sp.wrapping_add(1);
+	chip8.stack[chip8.register_sp as usize] = chip8.register_pc;
+	chip8.register_pc = entry.nnn();
+}
+
+fn skip_if_equal_imm(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if chip8.register_v[entry.x()] == entry.low_byte {
+		chip8.register_pc += 2;
+	}
+}
+
+fn skip_if_not_equal_imm(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if chip8.register_v[entry.x()] != entry.low_byte {
+		chip8.register_pc += 2;
+	}
+}
+
+fn skip_if_equal(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if chip8.register_v[entry.x()] == chip8.register_v[entry.y()] {
+		chip8.register_pc += 2;
+	}
+}
+
+fn load_imm(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] = entry.low_byte;
+}
+
+fn add_imm(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] = chip8.register_v[entry.x()].wrapping_add(entry.low_byte);
+}
+
+fn load(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] = chip8.register_v[entry.y()];
+}
+
+fn or(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] |= chip8.register_v[entry.y()];
+}
+
+fn and(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] &= chip8.register_v[entry.y()];
+}
+
+fn xor(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] ^= chip8.register_v[entry.y()];
+}
+
+fn add(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let result = chip8.register_v[entry.x()] as u16 + chip8.register_v[entry.y()] as u16;
+	chip8.register_v[entry.x()] = result as u8;
+	chip8.register_v[0xF] = (result > 0xFF) as u8;
+}
+
+fn sub(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let (x, y) = (entry.x(), entry.y());
+	chip8.register_v[0xF] = (chip8.register_v[x] >= chip8.register_v[y]) as u8;
+	chip8.register_v[x] = chip8.register_v[x].wrapping_sub(chip8.register_v[y]);
+}
+
+fn shift_right(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let source = if chip8.quirks.shift_vx { entry.x() } else { entry.y() };
+	chip8.register_v[0xF] = chip8.register_v[source] & 1;
+	chip8.register_v[entry.x()] = chip8.register_v[source] >> 1;
+}
+
+fn sub_reversed(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let (x, y) = (entry.x(), entry.y());
+	chip8.register_v[0xF] = (chip8.register_v[x] <= chip8.register_v[y]) as u8;
+	chip8.register_v[x] = chip8.register_v[y].wrapping_sub(chip8.register_v[x]);
+}
+
+fn shift_left(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let source = if chip8.quirks.shift_vx { entry.x() } else { entry.y() };
+	chip8.register_v[0xF] = chip8.register_v[source] >> 7;
+	chip8.register_v[entry.x()] = chip8.register_v[source] << 1;
+}
+
+fn skip_if_not_equal(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if chip8.register_v[entry.x()] != chip8.register_v[entry.y()] {
+		chip8.register_pc += 2;
+	}
+}
+
+fn load_i(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_i = entry.nnn();
+}
+
+fn jump_v0(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let offset = if chip8.quirks.jump_vx { chip8.register_v[entry.x()] } else { chip8.register_v[0] };
+	chip8.register_pc = entry.nnn() + offset as u16;
+}
+
+fn random(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] = chip8.random_byte() & entry.low_byte;
+}
+
+fn draw_sprite(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let n = entry.low_byte as usize & 0x0F;
+	let sprite = &chip8.memory[chip8.register_i as usize .. chip8.register_i as usize + n];
+	chip8.register_v[0xF] = chip8.display.draw_sprite(chip8.register_v[entry.x()], chip8.register_v[entry.y()], sprite) as u8;
+}
+
+fn skip_if_pressed(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if chip8.keyboard.is_pressed(chip8.register_v[entry.x()]) {
+		chip8.register_pc += 2;
+	}
+}
+
+fn skip_if_not_pressed(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if !chip8.keyboard.is_pressed(chip8.register_v[entry.x()]) {
+		chip8.register_pc += 2;
+	}
+}
+
+fn load_delay_timer(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_v[entry.x()] = chip8.register_dt;
+}
+
+fn wait_key_release(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	if !chip8.wait_key_release(entry.x() as u32) {
+		chip8.register_pc -= 2;
+		chip8.skip_to_next_frame(0);
+	}
+}
+
+fn set_delay_timer(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_dt = chip8.register_v[entry.x()];
+}
+
+fn set_sound_timer(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_st = chip8.register_v[entry.x()];
+}
+
+fn add_to_i(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_i += chip8.register_v[entry.x()] as u16;
+}
+
+fn load_font(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	chip8.register_i = chip8.register_v[entry.x()] as u16 * 5;
+}
+
+fn store_bcd(threaded_interpreter: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let address = chip8.register_i as usize;
+	let value = chip8.register_v[entry.x()];
+	chip8.memory[address] = value / 100;
+	chip8.memory[address + 1] = (value % 100) / 10;
+	chip8.memory[address + 2] = value % 10;
+	threaded_interpreter.invalidate(address, address + 3);
+}
+
+fn store_registers(threaded_interpreter: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	let address = chip8.register_i as usize;
+	for i in 0..(entry.x() + 1) {
+		chip8.memory[address + i] = chip8.register_v[i];
+	}
+	if !chip8.quirks.load_store_keep_i {
+		chip8.register_i += entry.x() as u16 + 1;
+	}
+	threaded_interpreter.invalidate(address, address + entry.x() + 1);
+}
+
+fn load_registers(_: &mut ThreadedInterpreter, chip8: &mut Chip8, entry: Entry) {
+	for i in 0..(entry.x() + 1) {
+		chip8.register_v[i] = chip8.memory[chip8.register_i as usize + i];
+	}
+	if !chip8.quirks.load_store_keep_i {
+		chip8.register_i += entry.x() as u16 + 1;
+	}
+}
@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+// palettes picked for each ROM, one "<ROM hash> = <palette>" per line
+const PALETTES_FILE: &str = "palettes";
+
+// $XDG_CONFIG_HOME/chip8dynarec, ~/.config/chip8dynarec or %APPDATA%\chip8dynarec
+pub fn directory() -> Option<PathBuf> {
+	let base = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+		.or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+		.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+	base.map(|base| base.join("chip8dynarec"))
+}
+
+fn read_entries(name: &str) -> Vec<(String, String)> {
+	let mut contents = String::new();
+	let path = match directory() {
+		Some(directory) => directory.join(name),
+		None => return Vec::new()
+	};
+	if File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).is_err() {
+		return Vec::new();
+	}
+
+	contents.lines().filter_map(|line| {
+		let mut parts = line.splitn(2, '=').map(str::trim);
+		match (parts.next(), parts.next()) {
+			(Some(key), Some(value)) if !key.starts_with('#') => Some((key.to_string(), value.to_string())),
+			_ => None
+		}
+	}).collect()
+}
+
+fn write_entry(name: &str, key: &str, value: &str) -> Result<(), String> {
+	let directory = directory().ok_or_else(|| "no configuration directory".to_string())?;
+	let mut entries = read_entries(name);
+	entries.retain(|entry| entry.0 != key);
+	entries.push((key.to_string(), value.to_string()));
+
+	let path = directory.join(name);
+	fs::create_dir_all(&directory)
+		.and_then(|_| File::create(&path))
+		.and_then(|mut file| entries.iter().try_for_each(|entry| writeln!(file, "{} = {}", entry.0, entry.1)))
+		.map_err(|error| format!("can't write '{}': {}", path.display(), error))
+}
+
+pub fn rom_palette(rom_hash: u64) -> Option<String> {
+	let key = format!("{:016x}", rom_hash);
+	read_entries(PALETTES_FILE).into_iter().find(|entry| entry.0 == key).map(|entry| entry.1)
+}
+
+pub fn remember_rom_palette(rom_hash: u64, palette: &str) -> Result<(), String> {
+	write_entry(PALETTES_FILE, &format!("{:016x}", rom_hash), palette)
+}
@@ -0,0 +1,96 @@
+#[cfg(feature="sdl")]
+use std::collections::VecDeque;
+
+// how sprites erased and drawn again in the next frame are kept on screen,
+// only the picture changes, collisions still use the emulated framebuffer
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Flicker {
+	#[default]
+	Off,
+	// turned off pixels fade out like phosphor, keeping this share of their brightness each frame
+	Decay(f32),
+	// pixels lit in any of the last frames are shown
+	Blend(usize)
+}
+
+const DEFAULT_DECAY: f32 = 0.6;
+const DEFAULT_BLEND_FRAMES: usize = 2;
+
+impl Flicker {
+	// off, decay[:percent kept] or blend[:frames]
+	pub fn parse(description: &str) -> Result<Flicker, String> {
+		let mut parts = description.splitn(2, ':');
+		let mode = parts.next().unwrap_or("");
+		let parameter = parts.next();
+		let error = || format!("'{}' isn't a flicker filter, expected off, decay[:percent] or blend[:frames]", description);
+
+		match (mode, parameter) {
+			("off", None) => Ok(Flicker::Off),
+			("decay", None) => Ok(Flicker::Decay(DEFAULT_DECAY)),
+			("decay", Some(percent)) => match percent.parse::<u32>() {
+				Ok(percent) if percent < 100 => Ok(Flicker::Decay(percent as f32 / 100.0)),
+				_ => Err(error())
+			},
+			("blend", None) => Ok(Flicker::Blend(DEFAULT_BLEND_FRAMES)),
+			("blend", Some(frames)) => match frames.parse::<usize>() {
+				Ok(frames) if frames > 0 => Ok(Flicker::Blend(frames)),
+				_ => Err(error())
+			},
+			_ => Err(error())
+		}
+	}
+}
+
+// what the frontend shows of each pixel: framebuffer value and brightness from 0 to 1
+#[cfg(feature="sdl")]
+pub struct FlickerFilter {
+	pixels: Vec<(u8, f32)>,
+	history: VecDeque<Vec<u8>>
+}
+
+#[cfg(feature="sdl")]
+impl FlickerFilter {
+	pub fn new() -> FlickerFilter {
+		FlickerFilter {
+			pixels: Vec::new(),
+			history: VecDeque::new()
+		}
+	}
+
+	pub fn apply(&mut self, flicker: Flicker, frame_buffer: &[u8]) -> &[(u8, f32)] {
+		if self.pixels.len() != frame_buffer.len() {
+			self.pixels = vec![(0, 0.0); frame_buffer.len()];
+			self.history.clear();
+		}
+
+		match flicker {
+			Flicker::Off => {
+				for (pixel, &value) in self.pixels.iter_mut().zip(frame_buffer) {
+					*pixel = (value, 1.0);
+				}
+			},
+			Flicker::Decay(decay) => {
+				for (pixel, &value) in self.pixels.iter_mut().zip(frame_buffer) {
+					*pixel = if value != 0 {
+						(value, 1.0)
+					} else if pixel.1 > 1.0 / 32.0 {
+						// fades in the color it was lit with
+						(pixel.0, pixel.1 * decay)
+					} else {
+						(0, 0.0)
+					};
+				}
+			},
+			Flicker::Blend(frames) => {
+				self.history.push_front(frame_buffer.to_vec());
+				self.history.truncate(frames);
+				for (index, pixel) in self.pixels.iter_mut().enumerate() {
+					let value = self.history.iter().fold(0, |value, frame| value | frame[index]);
+					*pixel = (value, 1.0);
+				}
+			}
+		}
+
+		&self.pixels
+	}
+}
@@ -0,0 +1,215 @@
+extern crate sdl2;
+
+use std::collections::HashMap;
+
+use self::sdl2::keyboard::Scancode;
+use self::sdl2::controller::{Axis, Button};
+
+// how far a stick is pushed before it presses a key
+pub const AXIS_THRESHOLD: i16 = 0x4000;
+
+// CHIP-8 keys in the COSMAC VIP hex pad layout:
+// 1 2 3 C
+// 4 5 6 D
+// 7 8 9 E
+// A 0 B F
+const HEX_PAD_KEYS: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+// the hex pad on the left of the keyboard, by position so it works with any layout
+const HEX_PAD: [Scancode; 16] = [
+	Scancode::Num1, Scancode::Num2, Scancode::Num3, Scancode::Num4,
+	Scancode::Q, Scancode::W, Scancode::E, Scancode::R,
+	Scancode::A, Scancode::S, Scancode::D, Scancode::F,
+	Scancode::Z, Scancode::X, Scancode::C, Scancode::V
+];
+
+// 0-9 on the numeric keypad, A-F on the letters
+const NUMPAD: [Scancode; 16] = [
+	Scancode::Kp0, Scancode::Kp1, Scancode::Kp2, Scancode::Kp3,
+	Scancode::Kp4, Scancode::Kp5, Scancode::Kp6, Scancode::Kp7,
+	Scancode::Kp8, Scancode::Kp9, Scancode::A, Scancode::B,
+	Scancode::C, Scancode::D, Scancode::E, Scancode::F
+];
+
+// the d-pad and left stick on 5/7/8/9 like WASD on the hex pad, A and B on 6 and 4
+const CONTROLLER: [(Input, u8); 12] = [
+	(Input::Button(Button::DPadUp), 0x5),
+	(Input::Button(Button::DPadLeft), 0x7),
+	(Input::Button(Button::DPadDown), 0x8),
+	(Input::Button(Button::DPadRight), 0x9),
+	(Input::Axis(Axis::LeftY, false), 0x5),
+	(Input::Axis(Axis::LeftX, false), 0x7),
+	(Input::Axis(Axis::LeftY, true), 0x8),
+	(Input::Axis(Axis::LeftX, true), 0x9),
+	(Input::Button(Button::A), 0x6),
+	(Input::Button(Button::B), 0x4),
+	(Input::Button(Button::X), 0xA),
+	(Input::Button(Button::Y), 0xB)
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Input {
+	Key(Scancode),
+	Button(Button),
+	// pushed towards the positive or negative side
+	Axis(Axis, bool)
+}
+
+impl Input {
+	// a scancode name, or a controller button or axis direction after "pad:", like pad:dpup or pad:leftx-
+	pub fn from_name(name: &str) -> Option<Input> {
+		if !name.starts_with("pad:") {
+			return Scancode::from_name(name).map(Input::Key);
+		}
+
+		let name = &name[4..];
+		if name.ends_with('+') || name.ends_with('-') {
+			let (axis, direction) = name.split_at(name.len() - 1);
+			Axis::from_string(axis).map(|axis| Input::Axis(axis, direction == "+"))
+		} else {
+			Button::from_string(name).map(Input::Button)
+		}
+	}
+}
+
+// host inputs bound to each CHIP-8 key, several inputs can press the same CHIP-8 key
+#[derive(Clone)]
+pub struct KeyMap {
+	bindings: HashMap<Input, u8>
+}
+
+impl KeyMap {
+	pub fn hex_pad() -> KeyMap {
+		KeyMap::with_keys(&HEX_PAD, &HEX_PAD_KEYS)
+	}
+
+	pub fn numpad() -> KeyMap {
+		let keys: Vec<u8> = (0..16).collect();
+		KeyMap::with_keys(&NUMPAD, &keys)
+	}
+
+	fn with_keys(scancodes: &[Scancode], keys: &[u8]) -> KeyMap {
+		let key_bindings = scancodes.iter().map(|&scancode| Input::Key(scancode)).zip(keys.iter().cloned());
+		KeyMap {
+			bindings: key_bindings.chain(CONTROLLER.iter().cloned()).collect()
+		}
+	}
+
+	pub fn preset(name: &str) -> Option<KeyMap> {
+		match name {
+			"hexpad" => Some(KeyMap::hex_pad()),
+			"numpad" => Some(KeyMap::numpad()),
+			_ => None
+		}
+	}
+
+	pub fn key(&self, input: Input) -> Option<u8> {
+		self.bindings.get(&input).cloned()
+	}
+
+	pub fn bindings(&self) -> &HashMap<Input, u8> {
+		&self.bindings
+	}
+
+	// replaces the bindings of a CHIP-8 key
+	pub fn bind(&mut self, key: u8, inputs: &[Input]) {
+		self.bindings.retain(|_, bound_key| *bound_key != key);
+		for input in inputs {
+			self.bindings.insert(*input, key);
+		}
+	}
+
+	// applies a key map file on top of this one, one binding per line:
+	//   # comment
+	//   preset = numpad
+	//   C = 4, Keypad 4, pad:start
+	// see Input::from_name for the names of the inputs, only the listed CHIP-8 keys change
+	pub fn load(&mut self, config: &str) -> Result<(), String> {
+		for (number, line) in config.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let error = |message: String| format!("line {}: {}", number + 1, message);
+			let mut parts = line.splitn(2, '=').map(str::trim);
+			let (name, value) = match (parts.next(), parts.next()) {
+				(Some(name), Some(value)) => (name, value),
+				_ => return Err(error(format!("expected '<key> = <inputs>', got '{}'", line)))
+			};
+
+			if name == "preset" {
+				*self = KeyMap::preset(value).ok_or_else(|| error(format!("unknown key map preset '{}', expected hexpad or numpad", value)))?;
+				continue;
+			}
+
+			let key = match u8::from_str_radix(name, 16) {
+				Ok(key) if key < 16 && name.len() == 1 => key,
+				_ => return Err(error(format!("'{}' isn't a CHIP-8 key, expected 0-F", name)))
+			};
+			let mut inputs = Vec::new();
+			for input in value.split(',').map(str::trim).filter(|input| !input.is_empty()) {
+				inputs.push(Input::from_name(input).ok_or_else(|| error(format!("unknown key or controller input '{}'", input)))?);
+			}
+			self.bind(key, &inputs);
+		}
+		Ok(())
+	}
+}
+
+impl Default for KeyMap {
+	fn default() -> KeyMap {
+		KeyMap::hex_pad()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn input_names() {
+		assert_eq!(Input::from_name("Q"), Some(Input::Key(Scancode::Q)));
+		assert_eq!(Input::from_name("Keypad 4"), Some(Input::Key(Scancode::Kp4)));
+		assert_eq!(Input::from_name("pad:dpup"), Some(Input::Button(Button::DPadUp)));
+		assert_eq!(Input::from_name("pad:start"), Some(Input::Button(Button::Start)));
+		assert_eq!(Input::from_name("pad:leftx-"), Some(Input::Axis(Axis::LeftX, false)));
+		assert_eq!(Input::from_name("pad:righttrigger+"), Some(Input::Axis(Axis::TriggerRight, true)));
+		assert_eq!(Input::from_name("pad:dpup+"), None);
+		assert_eq!(Input::from_name("pad:q"), None);
+		assert_eq!(Input::from_name("no such key"), None);
+	}
+
+	#[test]
+	fn load_replaces_listed_keys_only() {
+		let mut key_map = KeyMap::hex_pad();
+		key_map.load("# arrows\n5 = Up, pad:dpup\n\n8 = Down\n").unwrap();
+		assert_eq!(key_map.key(Input::Key(Scancode::Up)), Some(0x5));
+		assert_eq!(key_map.key(Input::Button(Button::DPadUp)), Some(0x5));
+		assert_eq!(key_map.key(Input::Key(Scancode::Down)), Some(0x8));
+		// the previous bindings of 5 and 8 are gone, the other keys keep theirs
+		assert_eq!(key_map.key(Input::Key(Scancode::W)), None);
+		assert_eq!(key_map.key(Input::Key(Scancode::S)), None);
+		assert_eq!(key_map.key(Input::Axis(Axis::LeftY, false)), None);
+		assert_eq!(key_map.key(Input::Key(Scancode::Num1)), Some(0x1));
+	}
+
+	#[test]
+	fn load_preset() {
+		let mut key_map = KeyMap::hex_pad();
+		key_map.load("preset = numpad\nA = Q").unwrap();
+		assert_eq!(key_map.key(Input::Key(Scancode::Kp7)), Some(0x7));
+		assert_eq!(key_map.key(Input::Key(Scancode::Q)), Some(0xA));
+		assert_eq!(key_map.key(Input::Key(Scancode::A)), None);
+	}
+
+	#[test]
+	fn load_errors() {
+		let mut key_map = KeyMap::hex_pad();
+		assert_eq!(key_map.load("5 Up").unwrap_err(), "line 1: expected '<key> = <inputs>', got '5 Up'");
+		assert_eq!(key_map.load("\nG = Up").unwrap_err(), "line 2: 'G' isn't a CHIP-8 key, expected 0-F");
+		assert_eq!(key_map.load("10 = Up").unwrap_err(), "line 1: '10' isn't a CHIP-8 key, expected 0-F");
+		assert_eq!(key_map.load("5 = Up, pad:nothing").unwrap_err(), "line 1: unknown key or controller input 'pad:nothing'");
+		assert_eq!(key_map.load("preset = azerty").unwrap_err(), "line 1: unknown key map preset 'azerty', expected hexpad or numpad");
+	}
+}
@@ -0,0 +1,17 @@
+mod chip8;
+#[cfg(feature="sdl")]
+mod sdlfrontend;
+#[cfg(feature="sdl")]
+mod keymap;
+mod palette;
+mod flicker;
+mod capture;
+
+pub use chip8::{rom_hash, Action, Chip8, Display, Engine, Frontend, Headless, Keyboard, Movie, Quirks, Rewind, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+#[cfg(feature="sdl")]
+pub use sdlfrontend::SdlFrontend;
+#[cfg(feature="sdl")]
+pub use keymap::{Input, KeyMap};
+pub use palette::Palette;
+pub use flicker::Flicker;
+pub use capture::{Image, Recorder};
@@ -1,7 +1,239 @@
-mod chip8;
+extern crate chip8dynarec;
+extern crate ctrlc;
+
+mod config;
+mod options;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use chip8dynarec::{rom_hash, Chip8, Display, Frontend, Image, Keyboard, Movie, Palette, Recorder, Rewind};
+#[cfg(feature="sdl")]
+use chip8dynarec::{KeyMap, SdlFrontend};
+
+use options::{Command, Options};
 
 fn main() {
-	let filename = std::env::args().nth(1).unwrap();
-	let mut chip8 = chip8::Chip8::new();
-	chip8.run(filename);
+	let options = match options::parse(std::env::args().skip(1)) {
+		Ok(Command::Run(options)) => options,
+		Ok(Command::Help) => {
+			println!("{}", options::USAGE);
+			return;
+		},
+		Err(error) => {
+			eprintln!("error: {}\n\n{}", error, options::USAGE);
+			process::exit(2);
+		}
+	};
+
+	if let Err(error) = run(&options) {
+		eprintln!("error: {}", error);
+		process::exit(1);
+	}
+}
+
+fn run(options: &Options) -> Result<(), String> {
+	let rom = read_rom(&options.rom).map_err(|error| format!("can't read ROM '{}': {}", options.rom, error))?;
+
+	let palette = load_palette(options, &rom)?;
+	let recorder = match options.record {
+		Some(ref path) => Some(Recorder::create(Path::new(path), palette, options.capture_scale)?),
+		None => None
+	};
+
+	let frontend: Box<dyn Frontend> = if options.headless {
+		Box::new(Interruptible::new(recorder)?)
+	} else {
+		window_frontend(options, palette, recorder)?
+	};
+
+	let mut chip8 = Chip8::new(frontend);
+	chip8.engine = options.engine;
+	chip8.quirks = options.quirks;
+	if let Some(cycles_per_frame) = options.cycles_per_frame {
+		chip8.cycles_per_frame = cycles_per_frame;
+	}
+	if let Some(seed) = options.seed {
+		chip8.seed = seed;
+	}
+	if options.trace {
+		chip8.trace = Some(Box::new(io::stdout()));
+	}
+	chip8.state_directory = config::directory().map(|directory| directory.join("states"));
+	chip8.rewind = if options.rewind_seconds > 0 { Some(Rewind::new(1, options.rewind_seconds as usize * 60)) } else { None };
+	chip8.load_rom(&rom)?;
+	if let Some(ref state) = options.load_state {
+		// a single digit is a slot of the hotkeys
+		let path = match state.parse::<u8>() {
+			Ok(slot) if (1..=9).contains(&slot) => chip8.state_slot_path(slot).ok_or_else(|| "no directory for save states".to_string())?,
+			_ => PathBuf::from(state)
+		};
+		chip8.load_state_file(&path)?;
+	}
+	if let Some(ref path) = options.play_movie {
+		chip8.play_movie(read_movie(path, chip8.cycles_per_frame)?)?;
+	}
+	if options.record_movie.is_some() {
+		chip8.record_movie(options.movie_hashes);
+	}
+	// headless movies play to their end
+	let frames = match chip8.movie() {
+		Some(movie) if options.frames.is_none() && options.headless && movie.is_playing() => movie.length,
+		_ => options.frames
+	};
+
+	let start = Instant::now();
+	match frames {
+		Some(frames) => {
+			for _ in 0..frames {
+				if chip8.has_quit() {
+					break;
+				}
+				chip8.run_frame();
+			}
+		},
+		None => chip8.run()
+	}
+
+	if let Some(ref path) = options.record_movie {
+		if let Some(movie) = chip8.stop_movie() {
+			File::create(path).and_then(|mut file| write!(file, "{}", movie))
+				.map_err(|error| format!("can't save the movie to '{}': {}", path, error))?;
+		}
+	}
+	if let Some(frame) = chip8.movie().and_then(Movie::desync) {
+		return Err(format!("the movie desynced at frame {}", frame));
+	}
+	if let Some(ref path) = options.screenshot {
+		Image::capture(&chip8.display, palette, options.capture_scale).save_png(Path::new(path))?;
+	}
+	if let Some(ref mut trace) = chip8.trace {
+		trace.flush().map_err(|error| format!("can't write the trace: {}", error))?;
+	}
+	if options.stats {
+		let elapsed = start.elapsed();
+		let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+		eprintln!("{} frames, {} instructions in {:.2}s", chip8.frame_count(), chip8.cycle_count(), seconds);
+	}
+	Ok(())
+}
+
+// no window, stopped with Ctrl-C at the end of a frame instead of being killed
+struct Interruptible {
+	interrupted: Arc<AtomicBool>,
+	recorder: Option<Recorder>
+}
+
+impl Interruptible {
+	fn new(recorder: Option<Recorder>) -> Result<Interruptible, String> {
+		let interrupted = Arc::new(AtomicBool::new(false));
+		let handler_interrupted = interrupted.clone();
+		ctrlc::set_handler(move || handler_interrupted.store(true, Ordering::SeqCst))
+			.map_err(|error| format!("can't handle Ctrl-C: {}", error))?;
+		Ok(Interruptible { interrupted, recorder })
+	}
+}
+
+impl Frontend for Interruptible {
+	fn update_key_states(&mut self, _: &mut Keyboard) {}
+
+	fn present(&mut self, display: &Display) {
+		let failed = match self.recorder {
+			Some(ref mut recorder) => recorder.record(display).err(),
+			None => None
+		};
+		if let Some(error) = failed {
+			eprintln!("recording stopped: {}", error);
+			self.recorder = None;
+		}
+	}
+
+	fn is_closed(&self) -> bool {
+		self.interrupted.load(Ordering::SeqCst)
+	}
+}
+
+#[cfg(feature="sdl")]
+fn window_frontend(options: &Options, palette: Palette, recorder: Option<Recorder>) -> Result<Box<dyn Frontend>, String> {
+	let mut sdl_frontend = SdlFrontend::new(options.scale).map_err(|error| format!("can't open a window, pass --headless to run without one: {}", error))?;
+	sdl_frontend.key_map = load_key_map(options)?;
+	sdl_frontend.integer_scaling = options.integer_scaling;
+	sdl_frontend.palette = palette;
+	sdl_frontend.flicker = options.flicker;
+	sdl_frontend.capture_scale = options.capture_scale;
+	sdl_frontend.recorder = recorder;
+	if options.fullscreen {
+		sdl_frontend.set_fullscreen(true)?;
+	}
+	Ok(Box::new(sdl_frontend))
+}
+
+#[cfg(not(feature="sdl"))]
+fn window_frontend(_: &Options, _: Palette, _: Option<Recorder>) -> Result<Box<dyn Frontend>, String> {
+	Err("built without the sdl feature, pass --headless".to_string())
+}
+
+// the user's default bindings, the key map given on the command line, then the ROM's own bindings
+#[cfg(feature="sdl")]
+fn load_key_map(options: &Options) -> Result<KeyMap, String> {
+	let mut key_map = KeyMap::default();
+	if let Some(default_key_map) = config::directory().map(|directory| directory.join("keys")) {
+		if default_key_map.is_file() {
+			load_key_map_file(&mut key_map, &default_key_map)?;
+		}
+	}
+
+	if let Some(ref name) = options.key_map {
+		match KeyMap::preset(name) {
+			Some(preset) => key_map = preset,
+			None => load_key_map_file(&mut key_map, Path::new(name))?
+		}
+	}
+
+	let rom_key_map = Path::new(&options.rom).with_extension("keys");
+	if rom_key_map.is_file() {
+		load_key_map_file(&mut key_map, &rom_key_map)?;
+	}
+	Ok(key_map)
+}
+
+// the palette given on the command line is remembered for the next runs of the ROM
+fn load_palette(options: &Options, rom: &[u8]) -> Result<Palette, String> {
+	let rom_hash = rom_hash(rom);
+	match options.palette {
+		Some(ref palette) => {
+			if let Err(error) = config::remember_rom_palette(rom_hash, palette) {
+				eprintln!("palette not remembered: {}", error);
+			}
+			Palette::parse(palette)
+		},
+		None => Ok(config::rom_palette(rom_hash).and_then(|palette| Palette::parse(&palette).ok()).unwrap_or_default())
+	}
+}
+
+#[cfg(feature="sdl")]
+fn load_key_map_file(key_map: &mut KeyMap, path: &Path) -> Result<(), String> {
+	let mut config = String::new();
+	File::open(path).and_then(|mut file| file.read_to_string(&mut config))
+		.map_err(|error| format!("can't read key map '{}': {}", path.display(), error))?;
+	key_map.load(&config).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+fn read_movie(path: &str, default_cycles_per_frame: u32) -> Result<Movie, String> {
+	let mut text = String::new();
+	File::open(path).and_then(|mut file| file.read_to_string(&mut text))
+		.map_err(|error| format!("can't read movie '{}': {}", path, error))?;
+	Movie::parse(&text, default_cycles_per_frame).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn read_rom(filename: &str) -> io::Result<Vec<u8>> {
+	let mut rom = Vec::new();
+	File::open(filename)?.read_to_end(&mut rom)?;
+	Ok(rom)
 }
@@ -0,0 +1,175 @@
+use chip8dynarec::{Engine, Flicker, Palette, Quirks};
+
+pub const USAGE: &str = "usage: chip8dynarec [options] <rom>
+
+options:
+  -e, --engine <name>     interpreter, threaded or recompiler
+  -q, --quirks <preset>   vip (default) or schip
+  -i, --ipf <count>       instructions per frame (default 10)
+      --seed <number>     seed of the CXNN random numbers, random by default
+  -s, --scale <factor>    initial window scale (default 8)
+      --scaling <mode>    integer (default) or fit, to fill the window
+      --fullscreen        start in fullscreen, F11 or Alt+Enter toggle it
+  -p, --palette <colors>  high-contrast (default), octo, amber, green, lcd or colors
+                          like #000000,#FFFFFF, remembered for the ROM
+      --flicker <filter>  off (default), decay[:percent] to fade erased pixels out
+                          or blend[:frames] to show pixels lit in the last frames
+  -k, --keymap <map>      hexpad (default), numpad or a key map file,
+                          <rom>.keys next to the ROM overrides it
+      --screenshot <file> save the display as PNG on exit, F12 saves one any time
+      --record <file>     record every frame to a .gif or raw .y4m video,
+                          F10 starts and stops a GIF recording
+      --capture-scale <factor>
+                          size of screenshots and recordings (default 1)
+      --load-state <file> start from a save state, or the state in slot 1 to 9
+      --record-movie <file>
+                          record the keys of every frame from power-on
+      --movie-hashes      also record state hashes to detect desyncs
+      --play-movie <file> replay a recorded or hand-written movie, exits
+                          with an error if it desyncs
+      --rewind <seconds>  how far back ` rewinds (default 20), 0 turns it off
+      --headless          run without a window
+  -f, --frames <count>    exit after this many frames, as fast as possible
+  -t, --trace             print every executed instruction, not with the recompiler
+      --stats             print the frames and instructions run on exit
+  -h, --help              print this help";
+
+const DEFAULT_SCALE: u32 = 8;
+const DEFAULT_REWIND_SECONDS: u32 = 20;
+
+pub struct Options {
+	pub rom: String,
+	pub engine: Engine,
+	pub quirks: Quirks,
+	pub cycles_per_frame: Option<u32>,
+	pub seed: Option<u64>,
+	pub scale: u32,
+	pub integer_scaling: bool,
+	pub fullscreen: bool,
+	pub palette: Option<String>,
+	pub flicker: Flicker,
+	pub key_map: Option<String>,
+	pub screenshot: Option<String>,
+	pub record: Option<String>,
+	pub capture_scale: usize,
+	pub load_state: Option<String>,
+	pub rewind_seconds: u32,
+	pub record_movie: Option<String>,
+	pub movie_hashes: bool,
+	pub play_movie: Option<String>,
+	pub headless: bool,
+	pub frames: Option<u64>,
+	pub trace: bool,
+	pub stats: bool
+}
+
+pub enum Command {
+	Run(Box<Options>),
+	Help
+}
+
+pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Command, String> {
+	let mut rom = None;
+	let mut options = Options {
+		rom: String::new(),
+		engine: Engine::default(),
+		quirks: Quirks::default(),
+		cycles_per_frame: None,
+		seed: None,
+		scale: DEFAULT_SCALE,
+		integer_scaling: true,
+		fullscreen: false,
+		palette: None,
+		flicker: Flicker::default(),
+		key_map: None,
+		screenshot: None,
+		record: None,
+		capture_scale: 1,
+		load_state: None,
+		rewind_seconds: DEFAULT_REWIND_SECONDS,
+		record_movie: None,
+		movie_hashes: false,
+		play_movie: None,
+		headless: false,
+		frames: None,
+		trace: false,
+		stats: false
+	};
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"-h" | "--help" => return Ok(Command::Help),
+			"-e" | "--engine" => options.engine = value(&arg, args.next())?.parse()?,
+			"-q" | "--quirks" => {
+				let name = value(&arg, args.next())?;
+				options.quirks = Quirks::preset(&name).ok_or_else(|| format!("unknown quirks preset '{}', expected vip or schip", name))?;
+			},
+			"-i" | "--ipf" => options.cycles_per_frame = Some(number(&arg, args.next())?),
+			"--seed" => {
+				let seed = value(&arg, args.next())?;
+				options.seed = Some(seed.parse().map_err(|_| format!("--seed expects a number, got '{}'", seed))?);
+			},
+			"-s" | "--scale" => options.scale = number(&arg, args.next())?,
+			"--scaling" => {
+				options.integer_scaling = match value(&arg, args.next())?.as_str() {
+					"integer" => true,
+					"fit" => false,
+					mode => return Err(format!("unknown scaling mode '{}', expected integer or fit", mode))
+				};
+			},
+			"--fullscreen" => options.fullscreen = true,
+			"-p" | "--palette" => {
+				let palette = value(&arg, args.next())?;
+				Palette::parse(&palette)?;
+				options.palette = Some(palette);
+			},
+			"--flicker" => options.flicker = Flicker::parse(&value(&arg, args.next())?)?,
+			"-k" | "--keymap" => options.key_map = Some(value(&arg, args.next())?),
+			"--screenshot" => options.screenshot = Some(value(&arg, args.next())?),
+			"--record" => options.record = Some(value(&arg, args.next())?),
+			"--capture-scale" => options.capture_scale = number(&arg, args.next())?,
+			"--load-state" => options.load_state = Some(value(&arg, args.next())?),
+			"--rewind" => {
+				let seconds = value(&arg, args.next())?;
+				options.rewind_seconds = seconds.parse().map_err(|_| format!("--rewind expects a number of seconds, got '{}'", seconds))?;
+			},
+			"--record-movie" => options.record_movie = Some(value(&arg, args.next())?),
+			"--movie-hashes" => options.movie_hashes = true,
+			"--play-movie" => options.play_movie = Some(value(&arg, args.next())?),
+			"--headless" => options.headless = true,
+			"-f" | "--frames" => options.frames = Some(number(&arg, args.next())?),
+			"-t" | "--trace" => options.trace = true,
+			"--stats" => options.stats = true,
+			_ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+			_ if rom.is_some() => return Err(format!("unexpected argument '{}', only one ROM can be run", arg)),
+			_ => rom = Some(arg)
+		}
+	}
+
+	if options.trace && options.engine == Engine::Recompiler {
+		return Err("--trace needs the interpreter or threaded engine".to_string());
+	}
+
+	if options.record_movie.is_some() && options.play_movie.is_some() {
+		return Err("--record-movie and --play-movie can't be used together".to_string());
+	}
+	if options.load_state.is_some() && (options.record_movie.is_some() || options.play_movie.is_some()) {
+		return Err("movies start from power-on, not from --load-state".to_string());
+	}
+
+	options.rom = rom.ok_or_else(|| "no ROM file given".to_string())?;
+	Ok(Command::Run(Box::new(options)))
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+	value.ok_or_else(|| format!("{} expects a value", option))
+}
+
+// a strictly positive integer
+fn number<T: ::std::str::FromStr + Default + PartialOrd>(option: &str, value: Option<String>) -> Result<T, String> {
+	let value = self::value(option, value)?;
+	match value.parse::<T>() {
+		Ok(number) if number > T::default() => Ok(number),
+		_ => Err(format!("{} expects a positive number, got '{}'", option, value))
+	}
+}
@@ -0,0 +1,65 @@
+// 0xRRGGBB colors indexed by the framebuffer values: background, first plane, second plane, both planes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette {
+	pub colors: [u32; 4]
+}
+
+const PRESETS: [(&str, [u32; 4]); 5] = [
+	("high-contrast", [0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF]),
+	("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+	("amber", [0x1A1000, 0xFFB000, 0x996600, 0xFFD866]),
+	("green", [0x0A140A, 0x33FF66, 0x1A8033, 0xAAFFBB]),
+	("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F])
+];
+
+impl Palette {
+	pub fn preset(name: &str) -> Option<Palette> {
+		PRESETS.iter().find(|preset| preset.0 == name).map(|preset| Palette { colors: preset.1 })
+	}
+
+	pub fn preset_names() -> Vec<&'static str> {
+		PRESETS.iter().map(|preset| preset.0).collect()
+	}
+
+	// a preset name, or 2 or 4 comma separated colors like #000000,#FFFFFF
+	pub fn parse(description: &str) -> Result<Palette, String> {
+		if let Some(palette) = Palette::preset(description) {
+			return Ok(palette);
+		}
+
+		let mut colors = Vec::new();
+		for color in description.split(',').map(str::trim) {
+			let hex = color.strip_prefix('#').unwrap_or(color);
+			match u32::from_str_radix(hex, 16) {
+				Ok(rgb) if hex.len() == 6 => colors.push(rgb),
+				_ => return Err(format!("'{}' isn't a palette, expected {} or colors like #000000,#FFFFFF", description, Palette::preset_names().join(", ")))
+			}
+		}
+
+		match colors.len() {
+			// single plane: the other planes are drawn with the foreground
+			2 => Ok(Palette { colors: [colors[0], colors[1], colors[1], colors[1]] }),
+			4 => Ok(Palette { colors: [colors[0], colors[1], colors[2], colors[3]] }),
+			count => Err(format!("a palette has 2 or 4 colors, got {}", count))
+		}
+	}
+
+	pub fn rgb(&self, value: u8) -> (u8, u8, u8) {
+		let color = self.colors[value as usize & 3];
+		((color >> 16) as u8, (color >> 8) as u8, color as u8)
+	}
+
+	// mixed with the background, from 0 for the background to 1 for the color itself
+	pub fn faded_rgb(&self, value: u8, brightness: f32) -> (u8, u8, u8) {
+		let (r, g, b) = self.rgb(value);
+		let (background_r, background_g, background_b) = self.rgb(0);
+		let mix = |color: u8, background: u8| (background as f32 + (color as f32 - background as f32) * brightness).round() as u8;
+		(mix(r, background_r), mix(g, background_g), mix(b, background_b))
+	}
+}
+
+impl Default for Palette {
+	fn default() -> Palette {
+		Palette::preset("high-contrast").unwrap()
+	}
+}
@@ -0,0 +1,288 @@
+extern crate sdl2;
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use self::sdl2::video::{FullscreenType, Window, WindowContext};
+use self::sdl2::render::{Canvas, TextureCreator};
+use self::sdl2::pixels::PixelFormatEnum;
+use self::sdl2::rect::Rect;
+use self::sdl2::pixels::Color;
+use self::sdl2::event::Event;
+use self::sdl2::keyboard::{Keycode, Mod, LALTMOD, LSHIFTMOD, RALTMOD, RSHIFTMOD};
+use self::sdl2::GameControllerSubsystem;
+use self::sdl2::controller::GameController;
+
+use chip8::{Action, Display, Frontend, Keyboard, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use keymap::{Input, KeyMap, AXIS_THRESHOLD};
+use palette::Palette;
+use flicker::{Flicker, FlickerFilter};
+use capture::{Image, Recorder};
+
+const STATE_SLOT_KEYS: [Keycode; 9] = [Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8, Keycode::F9];
+
+// P pause, N step a frame, Backspace reset, Tab and ` held fast-forward and rewind, M slow motion,
+// F1 to F9 load the save state in that slot and save it with Shift
+fn hotkey_action(keycode: Keycode, keymod: Mod) -> Option<Action> {
+	if let Some(index) = STATE_SLOT_KEYS.iter().position(|&key| key == keycode) {
+		let slot = index as u8 + 1;
+		return Some(if keymod.intersects(LSHIFTMOD | RSHIFTMOD) { Action::SaveState(slot) } else { Action::LoadState(slot) });
+	}
+
+	match keycode {
+		Keycode::P => Some(Action::TogglePause),
+		Keycode::N => Some(Action::StepFrame),
+		Keycode::Backspace => Some(Action::Reset),
+		Keycode::Tab => Some(Action::FastForward(true)),
+		Keycode::Backquote => Some(Action::Rewind(true)),
+		Keycode::M => Some(Action::ToggleSlowMotion),
+		_ => None
+	}
+}
+
+// in the working directory, named after the time so that captures don't overwrite each other
+fn capture_path(extension: &str) -> String {
+	let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+	format!("chip8dynarec-{}.{}", seconds, extension)
+}
+
+pub struct SdlFrontend {
+	pub key_map: KeyMap,
+	// scales by whole numbers so that all the pixels have the same size, or fills the window
+	pub integer_scaling: bool,
+	pub palette: Palette,
+	pub flicker: Flicker,
+	// screenshots and recordings are the CHIP-8 resolution times this
+	pub capture_scale: usize,
+	// every presented frame is recorded, F10 starts and stops a GIF
+	pub recorder: Option<Recorder>,
+	// F12 saves the next presented frame
+	screenshot_requested: bool,
+	// the display unpacked for the flicker filter, one byte per pixel
+	frame_buffer: [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+	flicker_filter: FlickerFilter,
+	canvas: Canvas<Window>,
+	// the display texture borrows it, so it's created again for each redraw
+	texture_creator: TextureCreator<WindowContext>,
+	// what's on screen, redrawn only when it changes
+	presented_pixels: Vec<(u8, f32)>,
+	presented_palette: Palette,
+	presented_viewport: (usize, usize, f32, i32, i32),
+	// the window was exposed or resized
+	needs_redraw: bool,
+	events: sdl2::EventPump,
+	// None when SDL couldn't initialize controller support, the keyboard still works
+	controller_subsystem: Option<GameControllerSubsystem>,
+	controllers: Vec<GameController>,
+	closed: bool,
+	actions: VecDeque<Action>
+}
+
+impl SdlFrontend {
+	// the window starts at scale times the CHIP-8 resolution and can be resized
+	pub fn new(scale: u32) -> Result<SdlFrontend, String> {
+		let sdl_context = sdl2::init()?;
+		let video_subsystem = sdl_context.video()?;
+		let window = video_subsystem.window("chip8dynarec", DISPLAY_WIDTH as u32 * scale, DISPLAY_HEIGHT as u32 * scale)
+									.position_centered()
+									.resizable()
+									.build()
+									.map_err(|error| error.to_string())?;
+
+		let canvas = window.into_canvas().build().map_err(|error| error.to_string())?;
+		let texture_creator = canvas.texture_creator();
+
+		Ok(SdlFrontend {
+			key_map: KeyMap::default(),
+			integer_scaling: true,
+			palette: Palette::default(),
+			flicker: Flicker::default(),
+			capture_scale: 1,
+			recorder: None,
+			screenshot_requested: false,
+			frame_buffer: [0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+			flicker_filter: FlickerFilter::new(),
+			canvas,
+			texture_creator,
+			presented_pixels: Vec::new(),
+			presented_palette: Palette::default(),
+			presented_viewport: (0, 0, 0.0, 0, 0),
+			needs_redraw: true,
+			events: sdl_context.event_pump()?,
+			// controllers already plugged in are reported as added too
+			controller_subsystem: sdl_context.game_controller().ok(),
+			controllers: Vec::new(),
+			closed: false,
+			actions: VecDeque::new()
+		})
+	}
+
+	fn handle_events(&mut self) {
+		let events: Vec<Event> = self.events.poll_iter().collect();
+		for event in events {
+			match event {
+				// SDL turns Ctrl-C into a quit event too
+				Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => self.closed = true,
+				Event::Window { .. } => self.needs_redraw = true,
+				Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => self.toggle_fullscreen(),
+				Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. } if keymod.intersects(LALTMOD | RALTMOD) => self.toggle_fullscreen(),
+				Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => self.screenshot_requested = true,
+				Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => self.toggle_recording(),
+				Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => self.actions.extend(hotkey_action(keycode, keymod)),
+				Event::KeyUp { keycode: Some(Keycode::Tab), .. } => self.actions.push_back(Action::FastForward(false)),
+				Event::KeyUp { keycode: Some(Keycode::Backquote), .. } => self.actions.push_back(Action::Rewind(false)),
+				Event::ControllerDeviceAdded { which, .. } => {
+					if let Some(ref controller_subsystem) = self.controller_subsystem {
+						match controller_subsystem.open(which as u32) {
+							Ok(controller) => self.controllers.push(controller),
+							Err(error) => eprintln!("can't open controller {}: {}", which, error)
+						}
+					}
+				},
+				// removed controllers are identified by instance id, not device index
+				Event::ControllerDeviceRemoved { which, .. } => self.controllers.retain(|controller| controller.instance_id() != which),
+				_ => ()
+			}
+		}
+	}
+
+	// borderless at the desktop resolution, so that switching is instant
+	pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+		let fullscreen_type = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+		self.canvas.window_mut().set_fullscreen(fullscreen_type)
+	}
+
+	fn toggle_fullscreen(&mut self) {
+		let fullscreen = self.canvas.window().fullscreen_state() == FullscreenType::Off;
+		if let Err(error) = self.set_fullscreen(fullscreen) {
+			eprintln!("can't switch fullscreen mode: {}", error);
+		}
+	}
+
+	fn toggle_recording(&mut self) {
+		if self.recorder.take().is_some() {
+			println!("recording stopped");
+			return;
+		}
+		let path = capture_path("gif");
+		match Recorder::create(Path::new(&path), self.palette, self.capture_scale) {
+			Ok(recorder) => {
+				println!("recording to {}", path);
+				self.recorder = Some(recorder);
+			},
+			Err(error) => eprintln!("{}", error)
+		}
+	}
+
+	fn capture(&mut self, display: &Display) {
+		if self.screenshot_requested {
+			self.screenshot_requested = false;
+			let path = capture_path("png");
+			match Image::capture(display, self.palette, self.capture_scale).save_png(Path::new(&path)) {
+				Ok(()) => println!("saved {}", path),
+				Err(error) => eprintln!("{}", error)
+			}
+		}
+
+		let failed = match self.recorder {
+			Some(ref mut recorder) => recorder.record(display).err(),
+			None => None
+		};
+		if let Some(error) = failed {
+			eprintln!("recording stopped: {}", error);
+			self.recorder = None;
+		}
+	}
+
+	// size of a CHIP-8 pixel and position of the picture, centered with black borders
+	fn viewport(&self, display: &Display) -> (f32, i32, i32) {
+		let (width, height) = self.canvas.output_size().unwrap_or((DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32));
+		let scale = f32::min(width as f32 / display.width() as f32, height as f32 / display.height() as f32);
+		let scale = if self.integer_scaling { f32::max(scale.floor(), 1.0) } else { scale };
+		let x = (width as f32 - display.width() as f32 * scale) / 2.0;
+		let y = (height as f32 - display.height() as f32 * scale) / 2.0;
+		(scale, x.round() as i32, y.round() as i32)
+	}
+
+	fn is_pressed(&self, input: Input) -> bool {
+		self.controllers.iter().any(|controller| match input {
+			Input::Button(button) => controller.button(button),
+			Input::Axis(axis, true) => controller.axis(axis) > AXIS_THRESHOLD,
+			Input::Axis(axis, false) => controller.axis(axis) < -AXIS_THRESHOLD,
+			Input::Key(_) => false
+		})
+	}
+}
+
+impl Frontend for SdlFrontend {
+	fn update_key_states(&mut self, keyboard: &mut Keyboard) {
+		self.handle_events();
+
+		let mut key_states = [false; 16];
+		for scancode in self.events.keyboard_state().pressed_scancodes() {
+			if let Some(key) = self.key_map.key(Input::Key(scancode)) {
+				key_states[key as usize] = true;
+			}
+		}
+		for (&input, &key) in self.key_map.bindings() {
+			if self.is_pressed(input) {
+				key_states[key as usize] = true;
+			}
+		}
+		for (key, &pressed) in key_states.iter().enumerate() {
+			keyboard.set_key_state(key as u8, pressed);
+		}
+	}
+
+	// the framebuffer is converted to a texture scaled by the GPU in one copy, when it changed
+	fn present(&mut self, display: &Display) {
+		self.capture(display);
+
+		let (width, height) = (display.width(), display.height());
+		let (scale, left, top) = self.viewport(display);
+		display.copy_frame_buffer(&mut self.frame_buffer);
+		let pixels = self.flicker_filter.apply(self.flicker, &self.frame_buffer);
+
+		// nothing changed since the last present
+		let viewport = (width, height, scale, left, top);
+		if !self.needs_redraw && pixels == &self.presented_pixels[..] && self.palette == self.presented_palette && viewport == self.presented_viewport {
+			return;
+		}
+
+		let mut texture = match self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32) {
+			Ok(texture) => texture,
+			Err(error) => {
+				eprintln!("can't create the display texture: {}", error);
+				return;
+			}
+		};
+
+		let mut rgb = Vec::with_capacity(pixels.len() * 3);
+		for &(value, brightness) in pixels {
+			let (r, g, b) = self.palette.faded_rgb(value, brightness);
+			rgb.extend_from_slice(&[r, g, b]);
+		}
+
+		// black borders around the picture
+		self.canvas.set_draw_color(Color::RGB(0x00, 0x00, 0x00));
+		self.canvas.clear();
+		let destination = Rect::new(left, top, (width as f32 * scale).round() as u32, (height as f32 * scale).round() as u32);
+		let _ = texture.update(None, &rgb, width * 3);
+		let _ = self.canvas.copy(&texture, None, destination);
+		self.canvas.present();
+
+		self.presented_pixels = pixels.to_vec();
+		self.presented_palette = self.palette;
+		self.presented_viewport = viewport;
+		self.needs_redraw = false;
+	}
+
+	fn is_closed(&self) -> bool {
+		self.closed
+	}
+
+	fn next_action(&mut self) -> Option<Action> {
+		self.actions.pop_front()
+	}
+}
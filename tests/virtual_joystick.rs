@@ -0,0 +1,92 @@
+// controllers on a machine without a display or a pad: SDL_VIDEODRIVER=dummy and a
+// virtual joystick from SDL 2.0.14, the test fails on older SDLs unless CHIP8_SKIP_SDL_TESTS is set
+#![cfg(all(feature="sdl", target_os="linux"))]
+
+extern crate chip8dynarec;
+
+use std::env;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+use chip8dynarec::{Frontend, Keyboard, SdlFrontend};
+
+// SDL_JOYSTICK_TYPE_GAMECONTROLLER, mapped like a standard controller with the
+// buttons and axes in the order of SDL_GameControllerButton and SDL_GameControllerAxis
+const JOYSTICK_TYPE_GAMECONTROLLER: c_int = 1;
+const BUTTON_A: c_int = 0;
+const BUTTON_DPAD_UP: c_int = 11;
+const AXIS_LEFT_X: c_int = 0;
+
+type AttachVirtual = extern "C" fn(c_int, c_int, c_int, c_int) -> c_int;
+type JoystickOpen = extern "C" fn(c_int) -> *mut c_void;
+type SetVirtualButton = extern "C" fn(*mut c_void, c_int, u8) -> c_int;
+type SetVirtualAxis = extern "C" fn(*mut c_void, c_int, i16) -> c_int;
+
+extern "C" {
+	fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+// looked up at run time, the sdl2 bindings predate virtual joysticks
+fn sdl_function(name: &str) -> Option<*mut c_void> {
+	let name = CString::new(name).unwrap();
+	// RTLD_DEFAULT, the symbols of the libSDL2 the test is linked to
+	let function = unsafe { dlsym(ptr::null_mut(), name.as_ptr()) };
+	if function.is_null() { None } else { Some(function) }
+}
+
+// a missing SDL only passes when asked to
+fn skip(reason: &str) {
+	if env::var_os("CHIP8_SKIP_SDL_TESTS").is_none() {
+		panic!("{}, set CHIP8_SKIP_SDL_TESTS to skip the SDL tests", reason);
+	}
+	eprintln!("skipped, {}", reason);
+}
+
+fn pressed_keys(frontend: &mut SdlFrontend) -> u16 {
+	let mut keyboard = Keyboard::new();
+	frontend.update_key_states(&mut keyboard);
+	keyboard.key_states()
+}
+
+#[test]
+fn virtual_controller_presses_keys() {
+	env::set_var("SDL_VIDEODRIVER", "dummy");
+	let mut frontend = match SdlFrontend::new(1) {
+		Ok(frontend) => frontend,
+		Err(error) => return skip(&format!("SDL didn't start: {}", error))
+	};
+	let functions = (sdl_function("SDL_JoystickAttachVirtual"), sdl_function("SDL_JoystickOpen"),
+		sdl_function("SDL_JoystickSetVirtualButton"), sdl_function("SDL_JoystickSetVirtualAxis"));
+	let (attach_virtual, joystick_open, set_virtual_button, set_virtual_axis): (AttachVirtual, JoystickOpen, SetVirtualButton, SetVirtualAxis) = match functions {
+		(Some(attach_virtual), Some(joystick_open), Some(set_virtual_button), Some(set_virtual_axis)) => unsafe {
+			(mem::transmute::<*mut c_void, AttachVirtual>(attach_virtual), mem::transmute::<*mut c_void, JoystickOpen>(joystick_open),
+				mem::transmute::<*mut c_void, SetVirtualButton>(set_virtual_button), mem::transmute::<*mut c_void, SetVirtualAxis>(set_virtual_axis))
+		},
+		_ => return skip("SDL has no virtual joysticks before 2.0.14")
+	};
+
+	let device_index = attach_virtual(JOYSTICK_TYPE_GAMECONTROLLER, 6, 15, 0);
+	assert!(device_index >= 0, "the virtual joystick wasn't attached");
+	let joystick = joystick_open(device_index);
+	assert!(!joystick.is_null());
+
+	// the frontend opens the controller when it sees it plugged in
+	assert_eq!(pressed_keys(&mut frontend), 0);
+
+	set_virtual_button(joystick, BUTTON_A, 1);
+	assert_eq!(pressed_keys(&mut frontend), 1 << 0x6);
+	set_virtual_button(joystick, BUTTON_A, 0);
+	set_virtual_button(joystick, BUTTON_DPAD_UP, 1);
+	assert_eq!(pressed_keys(&mut frontend), 1 << 0x5);
+	set_virtual_button(joystick, BUTTON_DPAD_UP, 0);
+
+	// past the threshold only
+	set_virtual_axis(joystick, AXIS_LEFT_X, 0x1000);
+	assert_eq!(pressed_keys(&mut frontend), 0);
+	set_virtual_axis(joystick, AXIS_LEFT_X, 0x7FFF);
+	assert_eq!(pressed_keys(&mut frontend), 1 << 0x9);
+	set_virtual_axis(joystick, AXIS_LEFT_X, -0x7FFF);
+	assert_eq!(pressed_keys(&mut frontend), 1 << 0x7);
+}